@@ -0,0 +1,93 @@
+// On-disk cache of segmented cell graphs, so big input images
+// only need to be flooded the first time they're used.
+
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path;
+
+use image::{Image, Color};
+use snapshot;
+use world::Cell;
+
+const CACHE_DIR: &'static str = ".lifelike-cache";
+// Bumped whenever segmentation changes, so older files are rebuilt rather
+// than trusted. 2: colors differing only in alpha are separate cells.
+// 3: fully transparent pixels are holes rather than cells.
+// 4: diagonal separator lines keep the pixels either side apart.
+// 5: so do diagonal lines of transparent pixels.
+const CACHE_VERSION: &'static str = "5";
+
+// Everything that affects how an image is broken into cells.
+// FNV-1a, so keys are the same from one build to the next.
+pub fn key(image: &Image, wrap: bool, separators: &[Color], separator_width: u32) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    let mut add = |byte: u8| {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    };
+    for shift in [0u32, 8, 16, 24].iter() {
+        add((image.width >> *shift) as u8);
+        add((image.height >> *shift) as u8);
+    }
+    add(wrap as u8);
+    for shift in [0u32, 8, 16, 24].iter() {
+        add((separators.len() as u32 >> *shift) as u8);
+        add((separator_width >> *shift) as u8);
+    }
+    for separator in separators.iter() {
+        for byte in [separator.red, separator.green, separator.blue, separator.alpha].iter() {
+            add(*byte);
+        }
+    }
+    for byte in image.pixel_data.iter() {
+        add(*byte);
+    }
+    hash
+}
+
+pub fn path_for_key(key: u64) -> path::PathBuf {
+    path::Path::new(CACHE_DIR).join(format!("{:016x}.cells", key))
+}
+
+// `None` if there's no usable cache entry; a damaged one is reported and ignored.
+pub fn load(path: &path::Path, width: u32, height: u32) -> Option<(Vec<Cell>, Image)> {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(_) => return None,
+    };
+    let mut reader = snapshot::Reader::new(BufReader::new(file));
+    let res = read(&mut reader, width, height);
+    match res {
+        Ok(cells_and_boundaries) => Some(cells_and_boundaries),
+        Err(e) => {
+            eprintln!("Ignoring bad cell cache '{}': {}", path.display(), e);
+            None
+        },
+    }
+}
+
+fn read<R: io::BufRead>(reader: &mut snapshot::Reader<R>, width: u32, height: u32) -> Result<(Vec<Cell>, Image), String> {
+    if reader.expect("lifelike-cells")? != [CACHE_VERSION] {
+        return Err(reader.error("Unsupported cache version"));
+    }
+    let cells = snapshot::read_cells(reader, width, height, false)?;
+    let cell_boundaries = snapshot::read_boundaries(reader, width, height, false)?;
+    Ok((cells, cell_boundaries))
+}
+
+// Failing to write the cache isn't worth stopping for.
+pub fn save(path: &path::Path, cells: &[Cell], cell_boundaries: &Image) {
+    let res = fs::create_dir_all(CACHE_DIR)
+        .and_then(|_| fs::File::create(path))
+        .and_then(|file| {
+            let mut out = BufWriter::new(file);
+            writeln!(out, "lifelike-cells {}", CACHE_VERSION)?;
+            snapshot::write_cells(&mut out, cells)?;
+            snapshot::write_boundaries(&mut out, cell_boundaries)?;
+            out.flush()
+        });
+    match res {
+        Err(e) => eprintln!("Couldn't write cell cache '{}': {}", path.display(), e),
+        _ => {},
+    }
+}
@@ -17,32 +17,74 @@ pub struct Point {
 }
 
 impl Point {
-    pub fn neighbors(&self) -> [Point; 8] {
+    // Pixels sharing an edge with this one.
+    pub fn edge_neighbors(&self) -> [Point; 4] {
         [
-            Point{ x: self.x - 1, y: self.y - 1 },
             Point{ x: self.x,     y: self.y - 1 },
-            Point{ x: self.x + 1, y: self.y - 1 },
             Point{ x: self.x - 1, y: self.y     },
             Point{ x: self.x + 1, y: self.y     },
-            Point{ x: self.x - 1, y: self.y + 1 },
             Point{ x: self.x,     y: self.y + 1 },
+        ]
+    }
+
+    // Pixels touching only a corner of this one.
+    pub fn corner_neighbors(&self) -> [Point; 4] {
+        [
+            Point{ x: self.x - 1, y: self.y - 1 },
+            Point{ x: self.x + 1, y: self.y - 1 },
+            Point{ x: self.x - 1, y: self.y + 1 },
             Point{ x: self.x + 1, y: self.y + 1 },
         ]
     }
 }
 
 #[derive(Copy, Clone)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    // From "X,Y,WIDTH,HEIGHT".
+    pub fn parse(string: &str) -> Result<Rect, String> {
+        let numbers: Vec<&str> = string.split(',').map(|number| number.trim()).collect();
+        if numbers.len() != 4 {
+            return Err(format!("Expected 'X,Y,WIDTH,HEIGHT', not '{}'", string));
+        }
+        match (numbers[0].parse(), numbers[1].parse(), numbers[2].parse(), numbers[3].parse()) {
+            (Ok(x), Ok(y), Ok(width), Ok(height)) => Ok(Rect{ x: x, y: y, width: width, height: height }),
+            _ => Err(format!("Bad rectangle '{}'", string)),
+        }
+    }
+
+    pub fn contains(&self, point: Point) -> bool {
+        point.x >= self.x &&
+        point.y >= self.y &&
+        point.x < self.x + self.width as i32 &&
+        point.y < self.y + self.height as i32
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
 pub struct Color {
     pub red: u8,
     pub green: u8,
     pub blue: u8,
+    // 0 is fully transparent, 255 fully opaque.
+    pub alpha: u8,
 }
 
-impl PartialEq for Color {
-    fn eq(&self, other: &Color) -> bool {
-        self.red == other.red &&
-        self.green == other.green &&
-        self.blue == other.blue
+impl Color {
+    // From "#RRGGBB"; the "#" is optional.
+    pub fn parse(string: &str) -> Result<Color, String> {
+        let hex = string.trim().trim_start_matches('#');
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_digit(16)) {
+            return Err(format!("Expected a color like '#RRGGBB', not '{}'", string));
+        }
+        let channel = |start: usize| u8::from_str_radix(&hex[start..start + 2], 16).unwrap();
+        Ok(Color{ red: channel(0), green: channel(2), blue: channel(4), alpha: 255 })
     }
 }
 
@@ -52,11 +94,36 @@ impl Image {
             Err(m) => panic!(m),
             Ok(image) => image,
         };
-        println!("File dimensions: (width, height) = ({}, {}).", image.width, image.height);
-        let pixel_data = match image.pixels {
+        eprintln!("File dimensions: (width, height) = ({}, {}).", image.width, image.height);
+        // The png revision pinned in Cargo.toml has libpng expand palettes and
+        // grey to RGB (unpacking bit depths below 8), strip 16-bit channels to
+        // their high byte, and add alpha from tRNS or as opaque filler, so
+        // everything arrives as RGBA8. The other 8-bit types are filled out in
+        // case that changes, and the size is checked in case anything else slips
+        // through. The tests load fixtures of each kind.
+        let mut pixel_data: Vec<u8> = match image.pixels {
             PixelsByColorType::RGBA8(pixels) => pixels,
-            _ => panic!("Only handling RGBA8 input for now."),
+            PixelsByColorType::RGB8(pixels) => {
+                pixels.chunks(3).flat_map(|rgb| vec![rgb[0], rgb[1], rgb[2], 255]).collect()
+            },
+            PixelsByColorType::KA8(pixels) => {
+                pixels.chunks(2).flat_map(|ka| vec![ka[0], ka[0], ka[0], ka[1]]).collect()
+            },
+            PixelsByColorType::K8(pixels) => {
+                pixels.iter().flat_map(|&k| vec![k, k, k, 255]).collect()
+            },
         };
+        if pixel_data.len() != image.width as usize * image.height as usize * 4 {
+            panic!("Couldn't load '{}': unsupported PNG pixel format", path.display());
+        }
+        // Whatever color is hidden under fully transparent pixels shouldn't matter.
+        for pixel in pixel_data.chunks_mut(4) {
+            if pixel[3] == 0 {
+                pixel[0] = 0;
+                pixel[1] = 0;
+                pixel[2] = 0;
+            }
+        }
         Image {
             pixel_data: pixel_data,
             width: image.width,
@@ -82,12 +149,45 @@ impl Image {
         }
     }
 
+    // Nothing shows through where nothing is drawn.
+    pub fn transparent(width: u32, height: u32) -> Image {
+        Image {
+            width: width,
+            height: height,
+            pixel_data: repeat(0u8).take((width * height * 4) as usize).collect(),
+        }
+    }
+
+    // Blown up by a whole number, each pixel becoming a square block.
+    pub fn scaled(&self, factor: u32) -> Image {
+        let width = self.width * factor;
+        let height = self.height * factor;
+        let mut pixel_data = Vec::with_capacity((width * height * 4) as usize);
+        for row in self.pixel_data.chunks(self.width as usize * 4) {
+            let mut scaled_row = Vec::with_capacity(width as usize * 4);
+            for pixel in row.chunks(4) {
+                for _ in 0..factor {
+                    scaled_row.extend(pixel.iter().cloned());
+                }
+            }
+            for _ in 0..factor {
+                pixel_data.extend(scaled_row.iter().cloned());
+            }
+        }
+        Image {
+            pixel_data: pixel_data,
+            width: width,
+            height: height,
+        }
+    }
+
     pub fn color_at(&self, point: Point) -> Color {
         let pixel_offset = self.linear_index(point) * 4;
         Color{
             red: self.pixel_data[pixel_offset],
             green: self.pixel_data[pixel_offset + 1],
             blue: self.pixel_data[pixel_offset + 2],
+            alpha: self.pixel_data[pixel_offset + 3],
         }
     }
 
@@ -96,10 +196,45 @@ impl Image {
         self.pixel_data[pixel_offset] = color.red;
         self.pixel_data[pixel_offset + 1] = color.green;
         self.pixel_data[pixel_offset + 2] = color.blue;
-        self.pixel_data[pixel_offset + 3] = 255; // Fixed alpha for now.
+        self.pixel_data[pixel_offset + 3] = color.alpha;
     }
 
     pub fn linear_index(&self, point: Point) -> usize {
         point.y as usize * self.width as usize + point.x as usize
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_fixture(name: &str) -> Image {
+        Image::load_png(&path::Path::new(env!("CARGO_MANIFEST_DIR")).join("test_images").join(name))
+    }
+
+    fn rgba(image: &Image) -> Vec<(u8, u8, u8, u8)> {
+        image.pixel_data.chunks(4).map(|p| (p[0], p[1], p[2], p[3])).collect()
+    }
+
+    #[test]
+    fn loads_palette_with_transparency() {
+        let image = load_fixture("palette.png");
+        assert_eq!((image.width, image.height), (2, 2));
+        // Fully transparent pixels lose their color.
+        assert_eq!(rgba(&image), vec![(255, 0, 0, 255), (0, 255, 0, 128), (0, 0, 0, 0), (255, 255, 255, 255)]);
+    }
+
+    #[test]
+    fn loads_low_bit_depths() {
+        assert_eq!(rgba(&load_fixture("palette_2bit.png")), vec![(255, 255, 255, 255), (255, 0, 0, 255), (0, 255, 0, 255)]);
+        let on = (255, 255, 255, 255);
+        let off = (0, 0, 0, 255);
+        assert_eq!(rgba(&load_fixture("gray1.png")), vec![on, off, on, on, off, off, off, off]);
+    }
+
+    #[test]
+    fn loads_16_bit_channels() {
+        assert_eq!(rgba(&load_fixture("rgba16.png")), vec![(0x12, 0xff, 0x00, 0xff), (0xab, 0x80, 0x7f, 0x80)]);
+        assert_eq!(rgba(&load_fixture("gray16.png")), vec![(0x40, 0x40, 0x40, 255), (0xc0, 0xc0, 0xc0, 255)]);
+    }
+}
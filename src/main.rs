@@ -9,16 +9,29 @@ use std::iter::FromIterator;
 use std::string::String;
 
 use getopts::{Options, Matches};
+use rand::{thread_rng, Rng};
 
 mod world;
 mod image;
 mod window;
 mod world_builder;
+mod rule;
+mod spec;
+mod snapshot;
+mod cell_cache;
+mod stats;
+mod output;
+mod render;
 
-use image::Image;
+use image::{Image, Rect, Color};
+use world::World;
+use rule::{Rule, Neighborhood, Weighting, Thresholds};
+use spec::{Spec, Region};
+use stats::StatsWriter;
+use render::{Palette, RenderMode};
 
 fn print_usage(program: &str, opts: Options) {
-    let short_message = format!("Usage: {} [options] <input_file>", program);
+    let short_message = format!("Usage: {} [options] [<input_file>]", program);
     println!("{}", opts.usage(short_message.as_str()));
 }
 
@@ -32,19 +45,82 @@ fn get_u32_opt(matches: &Matches, opt_name: &str) -> Option<u32> {
     }
 }
 
+fn get_u64_opt(matches: &Matches, opt_name: &str) -> Option<u64> {
+    match matches.opt_str(opt_name) {
+        Some(string) => match string.trim().parse::<u64>() {
+            Ok(value) => Some(value),
+            Err(_) => panic!("Bad unsigned int arg"),
+        },
+        None => None,
+    }
+}
+
+// The counts from `new_rule` if there is one, and any neighborhood and
+// weighting given, keeping the rest from `rule`.
+fn override_rule(rule: &Rule, new_rule: Option<&Rule>, neighborhood: Option<Neighborhood>, weighting: Option<Weighting>) -> Rule {
+    let mut result = new_rule.unwrap_or(rule).clone();
+    result.set_neighborhood(neighborhood.unwrap_or(rule.neighborhood()));
+    result.set_weighting(weighting.unwrap_or(rule.weighting()));
+    result
+}
+
+// A flag and its opposite, e.g. "--wrap" and "--no-wrap"; `None` if neither is given.
+fn get_switch(matches: &Matches, on: &str, off: &str) -> Option<bool> {
+    match (matches.opt_present(on), matches.opt_present(off)) {
+        (true, true) => panic!("Can't combine --{} with --{}", on, off),
+        (true, false) => Some(true),
+        (false, true) => Some(false),
+        (false, false) => None,
+    }
+}
+
 fn main() {
     // Parse program arguments.
     let args: Vec<String> = Vec::from_iter(env::args());
     let program = args[0].clone();
     let mut opts = Options::new();
+    opts.optopt("s", "spec", "load settings from this spec file; other options override it", "FILE");
+    opts.optopt("r", "rule", "birth/survival rule in B/S or S/B notation, e.g. 'B3/S23' (default) or '23/3', with an optional third part for Generations rules, e.g. 'B2/S/C3'", "RULE");
+    opts.optopt("n", "neighborhood", "which neighbors to count: 'all' (default), 'edge' or 'corner'", "KIND");
     opts.optopt("", "smin", "minimum neighbors for existing cell to survive", "UINT");
     opts.optopt("", "smax", "maximum neighbors for existing cell to survive", "UINT");
     opts.optopt("", "rmin", "minimum neighbors for new cell to be born", "UINT");
     opts.optopt("", "rmax", "maximum neighbors for new cell to be born", "UINT");
     opts.optopt("f", "frames", "number of frames to render", "UINT");
     opts.optflag("w", "wrap", "treat image space as toroidal");
+    opts.optflag("", "no-wrap", "don't treat image space as toroidal, even if the spec does");
+    opts.optopt("", "separator", "colors of lines drawn between cells, which aren't cells themselves, e.g. '#000000,#404040'", "COLORS");
+    opts.optopt("", "separator-width", "thickest separator line that cells count as neighbors across (default 3)", "UINT");
+    opts.optflag("", "no-cache", "find cells in the input image from scratch, without using or updating the cache");
+    opts.optflag("", "cache", "use and update the cache, even if the spec doesn't");
+    opts.optopt("", "seed", "seed for the random initial state; random if not given", "UINT");
+    opts.optopt("d", "density", "fraction of cells to start alive at random (default 0.5)", "FRACTION");
+    opts.optopt("", "region", "only start cells alive at random within this rectangle", "X,Y,WIDTH,HEIGHT");
+    opts.optopt("", "region-mask", "only start cells alive at random where they're mostly dark in this image", "FILE");
+    opts.optopt("i", "initial-image", "start with cells alive where they're mostly dark in this image, which must be the same size as the input", "FILE");
     opts.optflag("p", "proportional", "weight neighbors by how many neighbors they have");
-    opts.optopt("o", "output-prefix", "write output frames to this file instead of rendering to screen", "STRING");
+    opts.optopt("b", "border-weighted", "weight neighbors by shared border length, with thresholds on the living fraction in place of the rule's counts, e.g. 'S0.2-0.5/B0.3-0.4'", "THRESHOLDS");
+    opts.optopt("", "render", "what to color cells by: 'state' (default), 'age' of living cells, 'activity' over the whole run, or 'tint' from the input image", "MODE");
+    opts.optopt("", "trail", "generations that dead cells take to fade out (default 0)", "UINT");
+    opts.optopt("", "palette", "colors to draw with: 'classic' (default), 'plain' (no boundaries), 'dark', 'blueprint' or 'amber'", "NAME");
+    opts.optopt("", "alive-color", "color of living cells, overriding the palette", "#RRGGBB");
+    opts.optopt("", "dead-color", "color of dead cells, overriding the palette", "#RRGGBB");
+    opts.optopt("", "boundary-color", "color of cell boundaries, or 'none' to not draw them, overriding the palette", "#RRGGBB");
+    opts.optopt("", "resume", "carry on from a snapshot instead of building a new world from an input image", "FILE");
+    opts.optopt("", "save", "write a snapshot to this file when the run finishes or the window is closed", "FILE");
+    opts.optopt("o", "output-prefix", "write output frames to files starting with this instead of rendering to screen; '-' writes y4m video to standard output", "STRING");
+    opts.optopt("", "output-dir", "directory to write output to (default 'image_out')", "DIR");
+    opts.optopt("", "output-template", "output file name, with any of {prefix}, {input}, {rule}, {seed}, and {frame} or {generation} for a file per frame (default '{prefix}{frame}.png')", "TEMPLATE");
+    opts.optopt("", "start-frame", "generations to run before writing the first frame (default 0)", "UINT");
+    opts.optopt("", "stride", "write every this many generations (default 1)", "UINT");
+    opts.optopt("", "format", "write output frames as 'png' files (default), a single 'gif' or 'apng' animation, or 'y4m' video (default for standard output)", "FORMAT");
+    opts.optopt("", "delay", "milliseconds between frames of an animation or video (default 100, i.e. 10 frames per second)", "UINT");
+    opts.optopt("", "loop", "number of times an animation plays, or 0 to play forever (default)", "UINT");
+    opts.optopt("", "scale", "blow output frames up by this whole number (default 1)", "UINT");
+    opts.optflag("", "stop-on-cycle", "stop writing frames once the world repeats a previous state");
+    opts.optflag("", "no-stop-on-cycle", "keep writing frames after the world repeats, even if the spec stops");
+    opts.optflag("", "interactive", "show the world in a window, even if the spec has output settings");
+    opts.optopt("", "stats-out", "write population, births and deaths for each generation to this .csv or .jsonl file", "FILE");
     opts.optflag("h", "help", "print usage information");
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => { m }
@@ -54,68 +130,335 @@ fn main() {
         print_usage(program.as_str(), opts);
         return;
     }
-    let input = if matches.free.len() == 1 {
-        matches.free[0].clone()
-    } else {
-        print_usage(program.as_str(), opts);
-        return;
+    let mut spec = match matches.opt_str("spec") {
+        Some(spec_file) => {
+            eprintln!("Loading spec '{}'.", spec_file);
+            Spec::load(&path::Path::new(&spec_file))
+        },
+        None => Spec::default(),
+    };
+
+    // Apply command line overrides.
+    match matches.free.len() {
+        0 => {},
+        1 => spec.input = Some(matches.free[0].clone()),
+        _ => {
+            print_usage(program.as_str(), opts);
+            return;
+        },
+    }
+    if let Some(frames) = get_u32_opt(&matches, "frames") {
+        spec.frames = frames;
+    }
+    if let Some(wrap) = get_switch(&matches, "wrap", "no-wrap") {
+        spec.wrap = wrap;
+    }
+    if let Some(string) = matches.opt_str("separator") {
+        spec.separators = string.split(',').map(|color| match Color::parse(color) {
+            Ok(color) => color,
+            Err(e) => panic!("Bad separator color: {}", e),
+        }).collect();
+    }
+    if let Some(separator_width) = get_u32_opt(&matches, "separator-width") {
+        spec.separator_width = separator_width;
+    }
+    if let Some(cache) = get_switch(&matches, "cache", "no-cache") {
+        spec.cache = cache;
+    }
+    if let Some(seed) = get_u64_opt(&matches, "seed") {
+        spec.seed = Some(seed);
+    }
+    if let Some(initial_image) = matches.opt_str("initial-image") {
+        spec.initial_image = Some(initial_image);
+    }
+    if let Some(string) = matches.opt_str("density") {
+        spec.density = match string.trim().parse::<f64>() {
+            Ok(density) if density >= 0.0 && density <= 1.0 => density,
+            _ => panic!("Bad density; expected a number from 0.0 to 1.0"),
+        };
+    }
+    match (matches.opt_str("region"), matches.opt_str("region-mask")) {
+        (Some(_), Some(_)) => panic!("Can't combine --region with --region-mask"),
+        (Some(string), None) => match Rect::parse(&string) {
+            Ok(rect) => spec.region = Some(Region::Rect(rect)),
+            Err(e) => panic!("Bad region: {}", e),
+        },
+        (None, Some(mask)) => spec.region = Some(Region::Mask(mask)),
+        (None, None) => {},
+    }
+    if let Some(string) = matches.opt_str("render") {
+        spec.render_mode = match RenderMode::parse(&string) {
+            Ok(render_mode) => render_mode,
+            Err(e) => panic!("Bad render mode: {}", e),
+        };
+    }
+    if let Some(trail) = get_u32_opt(&matches, "trail") {
+        spec.trail = trail;
+    }
+    if let Some(string) = matches.opt_str("palette") {
+        spec.palette = match Palette::parse(&string) {
+            Ok(palette) => palette,
+            Err(e) => panic!("Bad palette: {}", e),
+        };
+    }
+    if let Some(string) = matches.opt_str("alive-color") {
+        spec.palette.alive = match Color::parse(&string) {
+            Ok(color) => color,
+            Err(e) => panic!("Bad alive color: {}", e),
+        };
+    }
+    if let Some(string) = matches.opt_str("dead-color") {
+        spec.palette.dead = match Color::parse(&string) {
+            Ok(color) => color,
+            Err(e) => panic!("Bad dead color: {}", e),
+        };
+    }
+    if let Some(string) = matches.opt_str("boundary-color") {
+        spec.palette.boundary = match Palette::parse_boundary(&string) {
+            Ok(boundary) => boundary,
+            Err(e) => panic!("Bad boundary color: {}", e),
+        };
+    }
+    if let Some(output_prefix) = matches.opt_str("output-prefix") {
+        spec.output_prefix = Some(output_prefix);
+    }
+    if let Some(output_dir) = matches.opt_str("output-dir") {
+        spec.output_dir = Some(output_dir);
+    }
+    if let Some(output_template) = matches.opt_str("output-template") {
+        spec.output_template = Some(output_template);
+    }
+    if let Some(start_frame) = get_u64_opt(&matches, "start-frame") {
+        spec.start_frame = start_frame;
+    }
+    if let Some(stride) = get_u32_opt(&matches, "stride") {
+        if stride == 0 {
+            panic!("Bad stride; expected a whole number from 1 up");
+        }
+        spec.stride = stride;
+    }
+    if let Some(string) = matches.opt_str("format") {
+        spec.format = match output::Format::parse(&string) {
+            Ok(format) => Some(format),
+            Err(e) => panic!("Bad output format: {}", e),
+        };
+    }
+    if let Some(delay) = get_u32_opt(&matches, "delay") {
+        spec.delay = delay;
+    }
+    if let Some(loop_count) = get_u32_opt(&matches, "loop") {
+        spec.loop_count = loop_count;
+    }
+    if let Some(scale) = get_u32_opt(&matches, "scale") {
+        if scale == 0 {
+            panic!("Bad scale; expected a whole number from 1 up");
+        }
+        spec.scale = scale;
+    }
+    if let Some(stop_on_cycle) = get_switch(&matches, "stop-on-cycle", "no-stop-on-cycle") {
+        spec.stop_on_cycle = stop_on_cycle;
+    }
+    if let Some(stats_out) = matches.opt_str("stats-out") {
+        spec.stats_out = Some(stats_out);
+    }
+
+    // The old min/max flags are shorthand for a rule with contiguous ranges.
+    let has_range_opts = ["smin", "smax", "rmin", "rmax"].iter().any(|name| matches.opt_present(name));
+    let new_rule = match matches.opt_str("rule") {
+        Some(string) => {
+            if has_range_opts {
+                panic!("Can't combine --rule with --smin, --smax, --rmin or --rmax");
+            }
+            match Rule::parse(&string) {
+                Ok(rule) => Some(rule),
+                Err(e) => panic!("Bad rule: {}", e),
+            }
+        },
+        None if has_range_opts => Some(Rule::from_ranges(
+            get_u32_opt(&matches, "smin").unwrap_or(2),
+            get_u32_opt(&matches, "smax").unwrap_or(3),
+            get_u32_opt(&matches, "rmin").unwrap_or(3),
+            get_u32_opt(&matches, "rmax").unwrap_or(3),
+        )),
+        None => None,
+    };
+    let neighborhood = matches.opt_str("neighborhood").map(|string| match Neighborhood::parse(&string) {
+        Ok(neighborhood) => neighborhood,
+        Err(e) => panic!("Bad neighborhood: {}", e),
+    });
+    let weighting = match (matches.opt_present("proportional"), matches.opt_str("border-weighted")) {
+        (true, Some(_)) => panic!("Can't combine --proportional with --border-weighted"),
+        (true, None) => Some(Weighting::Degree),
+        (false, Some(string)) => match Thresholds::parse(&string) {
+            Ok(thresholds) => Some(Weighting::Border(thresholds)),
+            Err(e) => panic!("Bad border thresholds: {}", e),
+        },
+        (false, None) => None,
+    };
+    spec.rule = override_rule(&spec.rule, new_rule.as_ref(), neighborhood, weighting);
+
+    // What the world was made from, for naming output files.
+    let source = matches.opt_str("resume").or(spec.input.clone()).unwrap_or_default();
+    let source_name = path::Path::new(&source).file_stem().map(|stem| stem.to_string_lossy().into_owned());
+
+    let mut world = match matches.opt_str("resume") {
+        Some(snapshot_file) => {
+            // The snapshot has its own cells and state, so options for
+            // building a world would go unused.
+            if !matches.free.is_empty() {
+                panic!("Can't combine --resume with an input image");
+            }
+            let building_opts = [
+                "wrap", "no-wrap", "separator", "separator-width", "seed",
+                "initial-image", "density", "region", "region-mask",
+            ];
+            if let Some(name) = building_opts.iter().find(|name| matches.opt_present(name)) {
+                panic!("Can't combine --resume with --{}", name);
+            }
+
+            eprintln!("Resuming from snapshot '{}'.", snapshot_file);
+            let mut world = World::load_snapshot(&path::Path::new(&snapshot_file));
+            // The snapshot's rule, unless it's overridden here.
+            if new_rule.is_some() || neighborhood.is_some() || weighting.is_some() {
+                let rule = override_rule(world.rule(), new_rule.as_ref(), neighborhood, weighting);
+                eprintln!("Using rule {} instead of the snapshot's {}.", rule, world.rule());
+                world.set_rule(rule);
+            }
+            world
+        },
+        None => {
+            let input = match spec.input {
+                Some(ref input) => input.clone(),
+                None => {
+                    print_usage(program.as_str(), opts);
+                    return;
+                },
+            };
+            if spec.seed.is_none() {
+                // Keep it short enough to type, and to fit in a spec file.
+                let seed = thread_rng().gen::<u32>() as u64;
+                eprintln!("Using seed {}; pass --seed {} to repeat this run.", seed, seed);
+                spec.seed = Some(seed);
+            }
+            eprintln!(
+                "Using rule {} counting {} neighbors, weighted by {}.",
+                spec.rule, spec.rule.neighborhood(), spec.rule.weighting()
+            );
+
+            // Load example PNG image.
+            // let file = "examples/hex_square_tri_large.png";
+            // let file = "examples/cartesian_grid.png";
+            // let file = "examples/hex_grid.png";
+            eprintln!("Loading '{}'.", input);
+            let image = Image::load_png(&path::Path::new(&input));
+
+            let builder = world_builder::WorldBuilder::new(image, &spec);
+            builder.build()
+        },
     };
-    let is_interactive = !matches.opt_present("output-prefix");
-
-    let frames = get_u32_opt(&matches, "frames").unwrap_or(100);
-    let wrap = matches.opt_present("w");
-    let proportional = matches.opt_present("p");
-
-    let smin = get_u32_opt(&matches, "smin").unwrap_or(2);
-    let smax = get_u32_opt(&matches, "smax").unwrap_or(3);
-    let rmin = get_u32_opt(&matches, "rmin").unwrap_or(3);
-    let rmax = get_u32_opt(&matches, "rmax").unwrap_or(3);
-
-    // Load example PNG image.
-    // let file = "examples/hex_square_tri_large.png";
-    // let file = "examples/cartesian_grid.png";
-    // let file = "examples/hex_grid.png";
-    println!("Loading '{}'.", input);
-    let image = Image::load_png(&path::Path::new(&input));
-
-    // TODO: instead of these long constructors, have a big old 'worldspec' that gets passed to most.
-    let builder = world_builder::WorldBuilder::new(
-        image,
-        wrap,
-        smin,
-        smax,
-        rmin,
-        rmax,
-        proportional,
-    );
-    let mut world = builder.build();
+
+    world.set_palette(spec.palette);
+    world.set_render_mode(spec.render_mode, spec.trail);
+
+    let mut stats_out = spec.stats_out.as_ref().map(|stats_file| StatsWriter::create(stats_file));
 
     // Either show an interactive window, or run the world for a set amount
     // of frames, writing them out to files as we go.
-    if is_interactive {
-        let mut win = window::Window::new(world);
+    let has_output = spec.output_prefix.is_some() || spec.output_dir.is_some() || spec.output_template.is_some();
+    if matches.opt_present("interactive") || !has_output {
+        let mut win = window::Window::new(world, stats_out);
         win.run();
+        world = win.world;
     } else {
+        let output_prefix = spec.output_prefix.clone().unwrap_or("frame_".to_string());
+        let output_dir = spec.output_dir.clone().unwrap_or("image_out".to_string());
+        let to_stdout = match spec.output_template {
+            Some(ref template) => template == output::STDOUT,
+            None => output_prefix == output::STDOUT,
+        };
+        let format = spec.format.unwrap_or(if to_stdout { output::Format::Y4m } else { output::Format::PngSequence });
+        let template = match spec.output_template {
+            Some(ref template) => template.clone(),
+            None if to_stdout => output::STDOUT.to_string(),
+            None => output::FileNames::default_template(format).to_string(),
+        };
+
         // Ensure output directory exists.
-        let res = fs::create_dir_all(&path::Path::new("./image_out"));
-        match res {
-            Err(e) => {
-                panic!("Couldn't create output directory! {}", e)
-            },
-            _ => {},
+        if !to_stdout {
+            let res = fs::create_dir_all(&path::Path::new(&output_dir));
+            match res {
+                Err(e) => {
+                    panic!("Couldn't create output directory! {}", e)
+                },
+                _ => {},
+            }
         }
 
-        let output_prefix = matches.opt_str("output-prefix").unwrap_or("frame_".to_string());
+        let names = output::FileNames {
+            dir: output_dir,
+            template: template,
+            values: vec![
+                ("prefix", output_prefix),
+                ("input", source_name.unwrap_or("world".to_string())),
+                // Rules have slashes in them, which can't go in file names.
+                ("rule", world.rule().to_string().replace('/', "_")),
+                ("seed", spec.seed.map(|seed| seed.to_string()).unwrap_or("none".to_string())),
+            ],
+        };
+        let mut output = output::create(
+            format, names,
+            world.image().width * spec.scale, world.image().height * spec.scale,
+            spec.delay, spec.loop_count
+        );
+        let mut cycle_reported = false;
+
+        // A row for the starting state, then one for each generation stepped
+        // to, so the last one written matches the world we finish with.
+        if let Some(ref mut stats_out) = stats_out {
+            stats_out.write(&world.stats());
+        }
 
-        for frame in 0..frames {
-            world.update_world_image();
+        // Write every `stride`th generation once we reach `start_frame`.
+        let mut generation = 0;
+        let mut frame = 0;
+        while frame < spec.frames {
+            if generation >= spec.start_frame && (generation - spec.start_frame) % spec.stride as u64 == 0 {
+                world.update_world_image();
 
-            let frame_file = format!("image_out/{}{:0>8}.png", output_prefix, frame);
-            println!("Writing frame to '{}'.", frame_file);
-            world.image().save_png(&path::Path::new(&frame_file));
+                let world_generation = world.stats().generation;
+                if spec.scale == 1 {
+                    output.write_frame(world.image(), world_generation);
+                } else {
+                    output.write_frame(&world.image().scaled(spec.scale), world_generation);
+                }
+                frame += 1;
+            }
 
             world.step();
+            generation += 1;
+            if let Some(ref mut stats_out) = stats_out {
+                stats_out.write(&world.stats());
+            }
+
+            if let Some(cycle) = world.cycle() {
+                if !cycle_reported {
+                    eprintln!("Reached a cycle: {}.", cycle);
+                    cycle_reported = true;
+                }
+                if spec.stop_on_cycle {
+                    break;
+                }
+            }
+        }
+        output.finish();
+        if let Some(ref mut stats_out) = stats_out {
+            stats_out.flush();
         }
     }
+
+    if let Some(snapshot_file) = matches.opt_str("save") {
+        eprintln!("Saving snapshot to '{}'.", snapshot_file);
+        world.save_snapshot(&path::Path::new(&snapshot_file));
+    }
 }
 
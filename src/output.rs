@@ -0,0 +1,487 @@
+// Writers for the frames of a headless run: a numbered PNG per frame,
+// a single animated GIF or APNG, or a raw Y4M video stream for piping
+// into an encoder.
+
+extern crate gif;
+extern crate flate2;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path;
+
+use self::gif::SetParameter;
+use self::flate2::Compression;
+use self::flate2::write::ZlibEncoder;
+
+use image::Image;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Format {
+    PngSequence,
+    Gif,
+    Apng,
+    Y4m,
+}
+
+impl Format {
+    pub fn parse(string: &str) -> Result<Format, String> {
+        match string.trim() {
+            "png" => Ok(Format::PngSequence),
+            "gif" => Ok(Format::Gif),
+            "apng" => Ok(Format::Apng),
+            "y4m" => Ok(Format::Y4m),
+            _ => Err(format!("Expected 'png', 'gif', 'apng' or 'y4m', not '{}'", string)),
+        }
+    }
+}
+
+pub trait FrameWriter {
+    fn write_frame(&mut self, image: &Image, generation: u64);
+    // Called once after the last frame.
+    fn finish(&mut self);
+}
+
+// Where output goes: a directory, and a file name template with "{name}"
+// placeholders filled in from `values`. A file per frame can also use
+// "{frame}", counting written frames from 0, and "{generation}".
+pub struct FileNames {
+    pub dir: String,
+    pub template: String,
+    pub values: Vec<(&'static str, String)>,
+}
+
+impl FileNames {
+    // The template used if none is given.
+    pub fn default_template(format: Format) -> &'static str {
+        match format {
+            Format::PngSequence => "{prefix}{frame}.png",
+            Format::Gif => "{prefix}.gif",
+            Format::Apng => "{prefix}.png",
+            Format::Y4m => "{prefix}.y4m",
+        }
+    }
+
+    fn name(&self, frame: Option<(u32, u64)>) -> String {
+        let mut name = String::new();
+        let mut rest = &self.template[..];
+        while let Some(start) = rest.find('{') {
+            name.push_str(&rest[..start]);
+            let end = match rest[start..].find('}') {
+                Some(length) => start + length,
+                None => panic!("Unclosed '{{' in output file name template '{}'", self.template),
+            };
+            let key = &rest[start + 1..end];
+            let value = match (key, frame) {
+                ("frame", Some((frame, _))) => format!("{:0>8}", frame),
+                ("generation", Some((_, generation))) => format!("{:0>8}", generation),
+                ("frame", None) | ("generation", None) => {
+                    panic!("Can't use '{{{}}}' in the file name of a single output file", key)
+                },
+                _ => match self.values.iter().find(|&&(name, _)| name == key) {
+                    Some(&(_, ref value)) => value.clone(),
+                    None => panic!("Unknown placeholder '{{{}}}' in output file name template", key),
+                },
+            };
+            name.push_str(&value);
+            rest = &rest[end + 1..];
+        }
+        name.push_str(rest);
+        path::Path::new(&self.dir).join(name).to_string_lossy().into_owned()
+    }
+}
+
+// `delay` is in milliseconds; a `loop_count` of 0 plays animations forever.
+// A template of "-" sends a video stream to standard output.
+pub fn create(format: Format, names: FileNames, width: u32, height: u32, delay: u32, loop_count: u32) -> Box<dyn FrameWriter> {
+    if names.template == STDOUT && format != Format::Y4m {
+        panic!("Only y4m output can be written to standard output");
+    }
+    match format {
+        Format::PngSequence => {
+            if !names.template.contains("{frame}") && !names.template.contains("{generation}") {
+                panic!("Output file name template '{}' needs '{{frame}}' or '{{generation}}' to write a file per frame", names.template);
+            }
+            // Catch mistakes in the template before doing any work.
+            names.name(Some((0, 0)));
+            Box::new(PngSequenceWriter {
+                names: names,
+                frame: 0,
+            })
+        },
+        Format::Gif => {
+            let file_name = names.name(None);
+            eprintln!("Writing frames to '{}'.", file_name);
+            Box::new(GifWriter::create(&file_name, width, height, delay, loop_count))
+        },
+        Format::Apng => {
+            let file_name = names.name(None);
+            eprintln!("Writing frames to '{}'.", file_name);
+            Box::new(ApngWriter::create(file_name, width, height, delay, loop_count))
+        },
+        Format::Y4m => {
+            let (file_name, out): (String, Box<dyn Write>) = if names.template == STDOUT {
+                ("standard output".to_string(), Box::new(io::stdout()))
+            } else {
+                let file_name = names.name(None);
+                eprintln!("Writing frames to '{}'.", file_name);
+                match fs::File::create(&file_name) {
+                    Ok(file) => (file_name, Box::new(file)),
+                    Err(e) => panic!("Couldn't create '{}': {}", file_name, e),
+                }
+            };
+            Box::new(Y4mWriter::create(file_name, out, width, height, delay))
+        },
+    }
+}
+
+pub const STDOUT: &'static str = "-";
+
+struct PngSequenceWriter {
+    names: FileNames,
+    frame: u32,
+}
+
+impl FrameWriter for PngSequenceWriter {
+    fn write_frame(&mut self, image: &Image, generation: u64) {
+        let frame_file = self.names.name(Some((self.frame, generation)));
+        eprintln!("Writing frame to '{}'.", frame_file);
+        image.save_png(&path::Path::new(&frame_file));
+        self.frame += 1;
+    }
+
+    fn finish(&mut self) {}
+}
+
+struct GifWriter {
+    file_name: String,
+    // Dropping the encoder finishes the file.
+    encoder: Option<gif::Encoder<BufWriter<fs::File>>>,
+    width: u16,
+    height: u16,
+    // In hundredths of a second.
+    delay: u16,
+    frames: u32,
+}
+
+impl GifWriter {
+    fn create(file_name: &str, width: u32, height: u32, delay: u32, loop_count: u32) -> GifWriter {
+        if width > u16::max_value() as u32 || height > u16::max_value() as u32 {
+            panic!("Image is too big for a GIF: {}x{}", width, height);
+        }
+        let res = fs::File::create(file_name).and_then(|file| {
+            let mut encoder = gif::Encoder::new(BufWriter::new(file), width as u16, height as u16, &[])?;
+            // GIFs count repeats after the first time through, rather than plays.
+            match loop_count {
+                0 => encoder.set(gif::Repeat::Infinite)?,
+                1 => {},
+                n => encoder.set(gif::Repeat::Finite((n - 1).min(u16::max_value() as u32) as u16))?,
+            }
+            Ok(encoder)
+        });
+        let encoder = match res {
+            Ok(encoder) => encoder,
+            Err(e) => panic!("Couldn't create '{}': {}", file_name, e),
+        };
+        GifWriter {
+            file_name: file_name.to_string(),
+            encoder: Some(encoder),
+            width: width as u16,
+            height: height as u16,
+            delay: ((delay + 5) / 10).min(u16::max_value() as u32) as u16,
+            frames: 0,
+        }
+    }
+
+    // Use the image's own colours if there are few enough of them, which there
+    // usually are; otherwise fall back to quantizing.
+    fn frame(&self, image: &Image) -> gif::Frame<'static> {
+        let mut palette: Vec<u8> = Vec::new();
+        let mut indices: HashMap<[u8; 4], u8> = HashMap::new();
+        let mut transparent = None;
+        let mut buffer = Vec::with_capacity(image.pixel_data.len() / 4);
+        for pixel in image.pixel_data.chunks(4) {
+            let key = if pixel[3] == 0 { [0, 0, 0, 0] } else { [pixel[0], pixel[1], pixel[2], 255] };
+            let index = match indices.get(&key) {
+                Some(&index) => index,
+                None if indices.len() < 256 => {
+                    let index = indices.len() as u8;
+                    indices.insert(key, index);
+                    palette.extend(key[..3].iter().cloned());
+                    if key[3] == 0 {
+                        transparent = Some(index);
+                    }
+                    index
+                },
+                None => {
+                    let mut frame = gif::Frame::from_rgba(self.width, self.height, &mut image.pixel_data.clone());
+                    frame.delay = self.delay;
+                    frame.dispose = gif::DisposalMethod::Background;
+                    return frame;
+                },
+            };
+            buffer.push(index);
+        }
+
+        let mut frame = gif::Frame::default();
+        frame.width = self.width;
+        frame.height = self.height;
+        frame.delay = self.delay;
+        frame.dispose = gif::DisposalMethod::Background;
+        frame.transparent = transparent;
+        frame.palette = Some(palette);
+        frame.buffer = buffer.into();
+        frame
+    }
+}
+
+impl FrameWriter for GifWriter {
+    fn write_frame(&mut self, image: &Image, _generation: u64) {
+        let frame = self.frame(image);
+        let res = match self.encoder {
+            Some(ref mut encoder) => encoder.write_frame(&frame),
+            None => panic!("Can't write frames to '{}' after finishing it", self.file_name),
+        };
+        match res {
+            Err(e) => panic!("Couldn't write to '{}': {}", self.file_name, e),
+            _ => {},
+        }
+        self.frames += 1;
+    }
+
+    fn finish(&mut self) {
+        self.encoder = None;
+        if self.frames == 0 {
+            remove_empty_animation(&self.file_name);
+        }
+    }
+}
+
+// Frames are written as they come. The frame count comes first in an APNG,
+// and a run can stop early on a cycle, so it's patched in at the end.
+struct ApngWriter {
+    file_name: String,
+    // Closed once the file is finished.
+    out: Option<BufWriter<fs::File>>,
+    width: u32,
+    height: u32,
+    delay_numerator: u16,
+    delay_denominator: u16,
+    loop_count: u32,
+    frames: u32,
+    // Frame control and frame data chunks share one sequence.
+    sequence: u32,
+}
+
+// The signature and the IHDR chunk come before acTL.
+const APNG_ACTL_OFFSET: u64 = 8 + 25;
+
+impl ApngWriter {
+    fn create(file_name: String, width: u32, height: u32, delay: u32, loop_count: u32) -> ApngWriter {
+        let file = match fs::File::create(&file_name) {
+            Ok(file) => file,
+            Err(e) => panic!("Couldn't create '{}': {}", file_name, e),
+        };
+        let (delay_numerator, delay_denominator) = if delay <= u16::max_value() as u32 {
+            (delay as u16, 1000)
+        } else {
+            ((delay / 10).min(u16::max_value() as u32) as u16, 100)
+        };
+        let mut writer = ApngWriter {
+            file_name: file_name,
+            out: Some(BufWriter::new(file)),
+            width: width,
+            height: height,
+            delay_numerator: delay_numerator,
+            delay_denominator: delay_denominator,
+            loop_count: loop_count,
+            frames: 0,
+            sequence: 0,
+        };
+        let res = writer.write_header();
+        writer.check(res);
+        writer
+    }
+
+    fn out(&mut self) -> &mut BufWriter<fs::File> {
+        match self.out {
+            Some(ref mut out) => out,
+            None => panic!("Can't write frames to '{}' after finishing it", self.file_name),
+        }
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        self.out().write_all(b"\x89PNG\r\n\x1a\n")?;
+
+        let mut header = Vec::new();
+        push_u32(&mut header, self.width);
+        push_u32(&mut header, self.height);
+        // 8 bits per channel RGBA, no interlacing.
+        header.extend([8, 6, 0, 0, 0].iter().cloned());
+        write_chunk(self.out(), b"IHDR", &header)?;
+
+        self.write_animation_control()
+    }
+
+    fn write_animation_control(&mut self) -> io::Result<()> {
+        let mut animation_control = Vec::new();
+        push_u32(&mut animation_control, self.frames);
+        push_u32(&mut animation_control, self.loop_count);
+        write_chunk(self.out(), b"acTL", &animation_control)
+    }
+
+    fn write_frame_chunks(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut frame_control = Vec::new();
+        push_u32(&mut frame_control, self.sequence);
+        push_u32(&mut frame_control, self.width);
+        push_u32(&mut frame_control, self.height);
+        push_u32(&mut frame_control, 0);
+        push_u32(&mut frame_control, 0);
+        frame_control.extend([
+            (self.delay_numerator >> 8) as u8, self.delay_numerator as u8,
+            (self.delay_denominator >> 8) as u8, self.delay_denominator as u8,
+            // Each frame replaces the whole canvas.
+            0, 0,
+        ].iter().cloned());
+        write_chunk(self.out(), b"fcTL", &frame_control)?;
+        self.sequence += 1;
+
+        // The first frame doubles as the still image for viewers without APNG support.
+        if self.frames == 0 {
+            write_chunk(self.out(), b"IDAT", data)?;
+        } else {
+            let mut frame_data = Vec::with_capacity(data.len() + 4);
+            push_u32(&mut frame_data, self.sequence);
+            frame_data.extend(data.iter().cloned());
+            write_chunk(self.out(), b"fdAT", &frame_data)?;
+            self.sequence += 1;
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    // acTL keeps its size, so rewriting it in place leaves the rest of the file alone.
+    fn write_trailer(&mut self) -> io::Result<()> {
+        write_chunk(self.out(), b"IEND", &[])?;
+        self.out().seek(SeekFrom::Start(APNG_ACTL_OFFSET))?;
+        self.write_animation_control()?;
+        self.out().flush()
+    }
+
+    fn check(&self, res: io::Result<()>) {
+        match res {
+            Err(e) => panic!("Couldn't write to '{}': {}", self.file_name, e),
+            _ => {},
+        }
+    }
+}
+
+impl FrameWriter for ApngWriter {
+    fn write_frame(&mut self, image: &Image, _generation: u64) {
+        // Each row starts with its filter type; we don't filter.
+        let row_length = image.width as usize * 4;
+        let res = {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::Default);
+            image.pixel_data.chunks(row_length).fold(Ok(()), |res: io::Result<()>, row| {
+                res.and_then(|_| encoder.write_all(&[0])).and_then(|_| encoder.write_all(row))
+            }).and_then(|_| encoder.finish())
+        };
+        let data = match res {
+            Ok(data) => data,
+            Err(e) => panic!("Couldn't compress frame: {}", e),
+        };
+        let res = self.write_frame_chunks(&data);
+        self.check(res);
+    }
+
+    fn finish(&mut self) {
+        if self.frames == 0 {
+            self.out = None;
+            remove_empty_animation(&self.file_name);
+            return;
+        }
+        let res = self.write_trailer();
+        self.check(res);
+        self.out = None;
+    }
+}
+
+// Uncompressed 4:4:4 frames, which any encoder can take on standard input.
+struct Y4mWriter {
+    file_name: String,
+    out: BufWriter<Box<dyn Write>>,
+}
+
+impl Y4mWriter {
+    fn create(file_name: String, out: Box<dyn Write>, width: u32, height: u32, delay: u32) -> Y4mWriter {
+        let mut writer = Y4mWriter {
+            file_name: file_name,
+            out: BufWriter::new(out),
+        };
+        // The frame rate is a fraction, so any delay is exact.
+        let header = format!("YUV4MPEG2 W{} H{} F1000:{} Ip A1:1 C444\n", width, height, delay.max(1));
+        let res = writer.out.write_all(header.as_bytes());
+        writer.check(res);
+        writer
+    }
+
+    fn check(&self, res: io::Result<()>) {
+        match res {
+            Err(e) => panic!("Couldn't write to {}: {}", self.file_name, e),
+            _ => {},
+        }
+    }
+}
+
+impl FrameWriter for Y4mWriter {
+    fn write_frame(&mut self, image: &Image, _generation: u64) {
+        let pixel_count = image.pixel_data.len() / 4;
+        let mut planes = vec![0u8; pixel_count * 3];
+        for (index, pixel) in image.pixel_data.chunks(4).enumerate() {
+            // Video has no transparency; show it as white.
+            let alpha = pixel[3] as i32;
+            let blend = |channel: u8| (channel as i32 * alpha + 255 * (255 - alpha) + 127) / 255;
+            let (red, green, blue) = (blend(pixel[0]), blend(pixel[1]), blend(pixel[2]));
+            // BT.601, limited range.
+            planes[index] = (((66 * red + 129 * green + 25 * blue + 128) >> 8) + 16) as u8;
+            planes[pixel_count + index] = (((-38 * red - 74 * green + 112 * blue + 128) >> 8) + 128) as u8;
+            planes[2 * pixel_count + index] = (((112 * red - 94 * green - 18 * blue + 128) >> 8) + 128) as u8;
+        }
+        let res = self.out.write_all(b"FRAME\n").and_then(|_| self.out.write_all(&planes));
+        self.check(res);
+    }
+
+    fn finish(&mut self) {
+        let res = self.out.flush();
+        self.check(res);
+    }
+}
+
+fn push_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend([(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8].iter().cloned());
+}
+
+// An animation needs at least one frame, so rather than leave a broken file
+// behind, take it away again. The file must be closed first.
+fn remove_empty_animation(file_name: &str) {
+    eprintln!("No frames to write to '{}'.", file_name);
+    match fs::remove_file(file_name) {
+        Err(e) => panic!("Couldn't remove '{}': {}", file_name, e),
+        _ => {},
+    }
+}
+
+fn write_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    let mut length = Vec::new();
+    push_u32(&mut length, data.len() as u32);
+    out.write_all(&length)?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+
+    let mut crc = flate2::Crc::new();
+    crc.update(kind);
+    crc.update(data);
+    let mut checksum = Vec::new();
+    push_u32(&mut checksum, crc.sum());
+    out.write_all(&checksum)
+}
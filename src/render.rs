@@ -0,0 +1,167 @@
+// Choices about how the world is drawn, shared by output frames and the window.
+
+use image::Color;
+use rule::{DEAD, ALIVE};
+
+// What decides the color of each cell.
+#[derive(Copy, Clone, PartialEq)]
+pub enum RenderMode {
+    // The palette's alive and dead colors.
+    State,
+    // Living cells from hot to cool as they get older.
+    Age,
+    // Every cell by how often it has been born or died.
+    Activity,
+    // Each cell in its color from the input image: deep when alive
+    // and washed out when dead, so different kinds of tile stay visible.
+    Tint,
+}
+
+impl RenderMode {
+    pub fn parse(string: &str) -> Result<RenderMode, String> {
+        match string.trim() {
+            "state" => Ok(RenderMode::State),
+            "age" => Ok(RenderMode::Age),
+            "activity" => Ok(RenderMode::Activity),
+            "tint" => Ok(RenderMode::Tint),
+            _ => Err(format!("Expected 'state', 'age', 'activity' or 'tint', not '{}'", string)),
+        }
+    }
+}
+
+// Cells this old or older all get the coolest age color.
+const OLD_AGE: u32 = 100;
+
+#[derive(Copy, Clone)]
+pub struct Palette {
+    pub alive: Color,
+    pub dead: Color,
+    // Drawn over the edges of cells; `None` leaves them the color of their cell.
+    pub boundary: Option<Color>,
+}
+
+const NAMES: [&'static str; 5] = ["classic", "plain", "dark", "blueprint", "amber"];
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::named("classic").unwrap()
+    }
+}
+
+impl Palette {
+    pub fn named(name: &str) -> Option<Palette> {
+        let (alive, dead, boundary) = match name.trim() {
+            "classic" => ((63, 63, 63), (255, 255, 255), Some((127, 127, 127))),
+            "plain" => ((63, 63, 63), (255, 255, 255), None),
+            "dark" => ((230, 230, 230), (20, 20, 20), Some((70, 70, 70))),
+            "blueprint" => ((255, 255, 255), (22, 60, 130), Some((80, 120, 190))),
+            "amber" => ((255, 176, 0), (30, 20, 0), Some((90, 60, 10))),
+            _ => return None,
+        };
+        let color = |(red, green, blue)| Color{ red: red, green: green, blue: blue, alpha: 255 };
+        Some(Palette {
+            alive: color(alive),
+            dead: color(dead),
+            boundary: boundary.map(color),
+        })
+    }
+
+    pub fn parse(name: &str) -> Result<Palette, String> {
+        match Palette::named(name) {
+            Some(palette) => Ok(palette),
+            None => Err(format!("Unknown palette '{}'; expected one of {}", name, NAMES.join(", "))),
+        }
+    }
+
+    // "none" for no boundaries, otherwise a color.
+    pub fn parse_boundary(string: &str) -> Result<Option<Color>, String> {
+        match string.trim() {
+            "none" => Ok(None),
+            _ => Color::parse(string).map(Some),
+        }
+    }
+
+    // Dying states of Generations rules fade from alive to dead.
+    pub fn state_color(&self, state: u8, states: u8) -> Color {
+        if state == DEAD {
+            return self.dead;
+        }
+        let (step, steps) = (state as i32 - ALIVE as i32, states as i32 - ALIVE as i32);
+        let fade = |alive: u8, dead: u8| (alive as i32 + (dead as i32 - alive as i32) * step / steps) as u8;
+        Color{
+            red: fade(self.alive.red, self.dead.red),
+            green: fade(self.alive.green, self.dead.green),
+            blue: fade(self.alive.blue, self.dead.blue),
+            alpha: fade(self.alive.alpha, self.dead.alpha),
+        }
+    }
+
+    // A cell's source color, for `RenderMode::Tint`. Dying states fade like they do normally.
+    pub fn tint_color(&self, source: Color, state: u8, states: u8) -> Color {
+        let dead = mix(source, self.dead, 0.7);
+        if state == DEAD {
+            return dead;
+        }
+        // Push channels away from grey, then darken.
+        let grey = (source.red as f32 + source.green as f32 + source.blue as f32) / 3.0;
+        let saturate = |channel: u8| (grey + (channel as f32 - grey) * 1.5).max(0.0).min(255.0) as u8;
+        let black = Color{ red: 0, green: 0, blue: 0, alpha: 255 };
+        let saturated = Color{
+            red: saturate(source.red),
+            green: saturate(source.green),
+            blue: saturate(source.blue),
+            alpha: source.alpha,
+        };
+        let alive = mix(saturated, black, 0.35);
+        mix(alive, dead, (state - ALIVE) as f32 / (states - ALIVE) as f32)
+    }
+
+    // Cells that died `since_death` generations ago fade from halfway
+    // between alive and dead to dead over `trail` generations.
+    pub fn trail_color(&self, since_death: u32, trail: u32) -> Option<Color> {
+        if since_death >= trail {
+            return None;
+        }
+        let start = mix(self.alive, self.dead, 0.5);
+        Some(mix(start, self.dead, (since_death + 1) as f32 / (trail + 1) as f32))
+    }
+}
+
+// Newborn cells are hot and the oldest are cool, on a log scale so
+// that differences among young cells still show up.
+pub fn age_color(age: u32) -> Color {
+    let old = (age.max(1).min(OLD_AGE) as f32).ln() / (OLD_AGE as f32).ln();
+    heat(0.9 - 0.7 * old)
+}
+
+// `flips` births and deaths over `generations`; a cell can flip at most once a generation.
+pub fn activity_color(flips: u32, generations: u64) -> Color {
+    if generations == 0 {
+        return heat(0.0);
+    }
+    heat((flips as f32 / generations as f32).sqrt())
+}
+
+// From dark indigo at 0.0 through red to pale yellow at 1.0.
+pub fn heat(t: f32) -> Color {
+    let stops = [
+        Color{ red: 20, green: 11, blue: 52, alpha: 255 },
+        Color{ red: 120, green: 28, blue: 109, alpha: 255 },
+        Color{ red: 212, green: 72, blue: 66, alpha: 255 },
+        Color{ red: 250, green: 160, blue: 40, alpha: 255 },
+        Color{ red: 252, green: 255, blue: 164, alpha: 255 },
+    ];
+    let position = t.max(0.0).min(1.0) * (stops.len() - 1) as f32;
+    let index = (position as usize).min(stops.len() - 2);
+    mix(stops[index], stops[index + 1], position - index as f32)
+}
+
+pub fn mix(from: Color, to: Color, t: f32) -> Color {
+    let channel = |from: u8, to: u8| (from as f32 + (to as f32 - from as f32) * t).round() as u8;
+    Color{
+        red: channel(from.red, to.red),
+        green: channel(from.green, to.green),
+        blue: channel(from.blue, to.blue),
+        alpha: channel(from.alpha, to.alpha),
+    }
+}
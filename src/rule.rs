@@ -0,0 +1,393 @@
+use std::fmt;
+
+use world::Adjacency;
+
+// Cell states. Anything from 2 up to the rule's state count
+// is a "dying" (refractory) state in a Generations rule.
+pub const DEAD: u8 = 0;
+pub const ALIVE: u8 = 1;
+
+// Birth/survival rule, as written in the usual "B3/S23" notation,
+// or "B2/S345/C4" for a Generations rule with dying states.
+#[derive(Clone)]
+pub struct Rule {
+    // Living neighbor counts at which a dead cell is born.
+    birth: Vec<u32>,
+    // Living neighbor counts at which a living cell survives.
+    survival: Vec<u32>,
+    // Total number of cell states, including dead and alive.
+    // Plain life-like rules have exactly two.
+    states: u8,
+    // Which neighbors are counted, and how much each one counts for;
+    // neither is part of the rule notation.
+    neighborhood: Neighborhood,
+    weighting: Weighting,
+}
+
+// Which kinds of neighbor a rule counts, e.g. `Edge` for
+// von Neumann-style rules on arbitrary tilings.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Neighborhood {
+    All,
+    Edge,
+    Corner,
+}
+
+impl Neighborhood {
+    pub fn parse(string: &str) -> Result<Neighborhood, String> {
+        match string.trim() {
+            "all" => Ok(Neighborhood::All),
+            "edge" => Ok(Neighborhood::Edge),
+            "corner" => Ok(Neighborhood::Corner),
+            _ => Err(format!("Unknown neighborhood '{}'; expected 'all', 'edge' or 'corner'", string)),
+        }
+    }
+
+    pub fn includes(&self, adjacency: Adjacency) -> bool {
+        match *self {
+            Neighborhood::All => true,
+            Neighborhood::Edge => adjacency == Adjacency::Edge,
+            Neighborhood::Corner => adjacency == Adjacency::Corner,
+        }
+    }
+}
+
+// How much each living neighbor contributes.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Weighting {
+    // Every living neighbor counts once.
+    Count,
+    // Living neighbors count for as many neighbors as they have themselves,
+    // scaled by the cell's own neighbor count.
+    Degree,
+    // Living neighbors count by the length of border they share with the cell,
+    // as a fraction of its whole border. Birth and survival are then decided by
+    // these thresholds instead of the rule's neighbor counts.
+    Border(Thresholds),
+}
+
+impl Weighting {
+    // The same form as it's displayed: "count", "degree" or "border <thresholds>".
+    pub fn parse(string: &str) -> Result<Weighting, String> {
+        let string = string.trim();
+        match string {
+            "count" => Ok(Weighting::Count),
+            "degree" => Ok(Weighting::Degree),
+            _ if string.starts_with("border ") => {
+                Thresholds::parse(&string["border ".len()..]).map(Weighting::Border)
+            },
+            _ => Err(format!("Unknown weighting '{}'; expected 'count', 'degree' or 'border <thresholds>'", string)),
+        }
+    }
+}
+
+impl fmt::Display for Weighting {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Weighting::Count => write!(f, "count"),
+            Weighting::Degree => write!(f, "degree"),
+            Weighting::Border(ref thresholds) => write!(f, "border {}", thresholds),
+        }
+    }
+}
+
+// Inclusive ranges of living-border fraction for survival and birth.
+#[derive(Copy, Clone, PartialEq)]
+pub struct Thresholds {
+    pub survival_min: f32,
+    pub survival_max: f32,
+    pub birth_min: f32,
+    pub birth_max: f32,
+}
+
+impl Thresholds {
+    // Written like a rule, with ranges in place of counts: "S0.2-0.5/B0.3-0.4".
+    pub fn parse(string: &str) -> Result<Thresholds, String> {
+        let mut survival = None;
+        let mut birth = None;
+        for part in string.trim().split('/') {
+            let mut chars = part.chars();
+            let letter = chars.next().map(|c| c.to_ascii_uppercase());
+            let range = match parse_fraction_range(chars.as_str()) {
+                Ok(range) => range,
+                Err(e) => return Err(format!("{} in thresholds '{}'", e, string)),
+            };
+            match letter {
+                Some('S') if survival.is_none() => survival = Some(range),
+                Some('B') if birth.is_none() => birth = Some(range),
+                _ => return Err(format!("Expected one 'S' part and one 'B' part in thresholds '{}'", string)),
+            }
+        }
+        match (survival, birth) {
+            (Some((survival_min, survival_max)), Some((birth_min, birth_max))) => Ok(Thresholds {
+                survival_min: survival_min,
+                survival_max: survival_max,
+                birth_min: birth_min,
+                birth_max: birth_max,
+            }),
+            _ => Err(format!("Expected one 'S' part and one 'B' part in thresholds '{}'", string)),
+        }
+    }
+
+    pub fn born(&self, living_fraction: f32) -> bool {
+        living_fraction >= self.birth_min && living_fraction <= self.birth_max
+    }
+
+    pub fn survives(&self, living_fraction: f32) -> bool {
+        living_fraction >= self.survival_min && living_fraction <= self.survival_max
+    }
+}
+
+impl fmt::Display for Thresholds {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "S{}-{}/B{}-{}", self.survival_min, self.survival_max, self.birth_min, self.birth_max)
+    }
+}
+
+impl fmt::Display for Neighborhood {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Neighborhood::All => "all",
+            Neighborhood::Edge => "edge",
+            Neighborhood::Corner => "corner",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Rule {
+    // Shorthand for the old contiguous-range rules (`--smin`, `--smax`, etc.).
+    pub fn from_ranges(smin: u32, smax: u32, rmin: u32, rmax: u32) -> Rule {
+        Rule {
+            birth: (rmin..rmax + 1).collect(),
+            survival: (smin..smax + 1).collect(),
+            states: 2,
+            neighborhood: Neighborhood::All,
+            weighting: Weighting::Count,
+        }
+    }
+
+    // Accepts both B/S notation ("B36/S23") and the older S/B notation ("23/36"),
+    // each with an optional third part giving the number of states for
+    // a Generations rule ("B2/S/C3" or "/2/3" for Brian's Brain).
+    //
+    // Counts are normally single digits, but a list containing commas
+    // is read as comma-separated numbers instead (e.g. "B3/S2,10,11") so
+    // that cells with lots of neighbors can still be described.
+    pub fn parse(string: &str) -> Result<Rule, String> {
+        let parts: Vec<&str> = string.trim().split('/').collect();
+        if parts.len() != 2 && parts.len() != 3 {
+            return Err(format!("Expected two or three '/'-separated parts in rule '{}'", string));
+        }
+
+        let mut birth = None;
+        let mut survival = None;
+        let lettered = parts[..2].iter().any(|part| part.starts_with(|c: char| c.is_alphabetic()));
+        if lettered {
+            for part in parts[..2].iter() {
+                let mut chars = part.chars();
+                let letter = chars.next().map(|c| c.to_ascii_uppercase());
+                let counts = match parse_counts(chars.as_str()) {
+                    Ok(counts) => counts,
+                    Err(e) => return Err(format!("{} in rule '{}'", e, string)),
+                };
+                match letter {
+                    Some('B') if birth.is_none() => birth = Some(counts),
+                    Some('S') if survival.is_none() => survival = Some(counts),
+                    _ => return Err(format!("Expected one 'B' part and one 'S' part in rule '{}'", string)),
+                }
+            }
+        } else {
+            // S/B notation: survival first.
+            for (i, part) in parts[..2].iter().enumerate() {
+                let counts = match parse_counts(part) {
+                    Ok(counts) => counts,
+                    Err(e) => return Err(format!("{} in rule '{}'", e, string)),
+                };
+                if i == 0 {
+                    survival = Some(counts);
+                } else {
+                    birth = Some(counts);
+                }
+            }
+        }
+
+        let states = if parts.len() == 3 {
+            let count = parts[2].trim_start_matches(|c: char| c == 'C' || c == 'c' || c == 'G' || c == 'g');
+            match count.parse::<u8>() {
+                Ok(states) if states >= 2 => states,
+                _ => return Err(format!("Bad state count '{}' in rule '{}'; expected 2 to 255", parts[2], string)),
+            }
+        } else {
+            2
+        };
+
+        Ok(Rule {
+            birth: birth.unwrap(),
+            survival: survival.unwrap(),
+            states: states,
+            neighborhood: Neighborhood::All,
+            weighting: Weighting::Count,
+        })
+    }
+
+    pub fn born(&self, living_neighbors: u32) -> bool {
+        self.birth.contains(&living_neighbors)
+    }
+
+    pub fn survives(&self, living_neighbors: u32) -> bool {
+        self.survival.contains(&living_neighbors)
+    }
+
+    pub fn states(&self) -> u8 {
+        self.states
+    }
+
+    pub fn neighborhood(&self) -> Neighborhood {
+        self.neighborhood
+    }
+
+    pub fn set_neighborhood(&mut self, neighborhood: Neighborhood) {
+        self.neighborhood = neighborhood;
+    }
+
+    pub fn weighting(&self) -> Weighting {
+        self.weighting
+    }
+
+    pub fn set_weighting(&mut self, weighting: Weighting) {
+        self.weighting = weighting;
+    }
+
+    // Living cells that fail to survive pass through each dying state in turn
+    // before they are dead; only dead cells can be born again.
+    pub fn next_state(&self, state: u8, survives: bool, born: bool) -> u8 {
+        if state == DEAD {
+            if born { ALIVE } else { DEAD }
+        } else if state == ALIVE && survives {
+            ALIVE
+        } else if state + 1 < self.states {
+            state + 1
+        } else {
+            DEAD
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "B{}/S{}", format_counts(&self.birth), format_counts(&self.survival))?;
+        if self.states > 2 {
+            write!(f, "/C{}", self.states)?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_counts(string: &str) -> Result<Vec<u32>, String> {
+    let mut counts: Vec<u32> = Vec::new();
+    if string.contains(',') {
+        for number in string.split(',').filter(|number| !number.trim().is_empty()) {
+            match number.trim().parse::<u32>() {
+                Ok(count) => counts.push(count),
+                Err(_) => return Err(format!("Bad neighbor count '{}'", number)),
+            }
+        }
+    } else {
+        for c in string.chars() {
+            match c.to_digit(10) {
+                Some(count) => counts.push(count),
+                None => return Err(format!("Bad neighbor count '{}'", c)),
+            }
+        }
+    }
+    counts.sort();
+    counts.dedup();
+    Ok(counts)
+}
+
+fn parse_fraction_range(string: &str) -> Result<(f32, f32), String> {
+    let bounds: Vec<&str> = string.split('-').collect();
+    if bounds.len() != 2 {
+        return Err(format!("Expected a range like '0.2-0.5', not '{}'", string));
+    }
+    match (bounds[0].trim().parse::<f32>(), bounds[1].trim().parse::<f32>()) {
+        (Ok(min), Ok(max)) => Ok((min, max)),
+        _ => Err(format!("Bad fraction range '{}'", string)),
+    }
+}
+
+fn format_counts(counts: &[u32]) -> String {
+    let strings: Vec<String> = counts.iter().map(|count| count.to_string()).collect();
+    if counts.iter().any(|count| *count > 9) {
+        // Trailing comma keeps a lone multi-digit count from being read as digits.
+        let mut string = strings.join(",");
+        if counts.len() == 1 {
+            string.push(',');
+        }
+        string
+    } else {
+        strings.concat()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rule;
+
+    fn counts(rule: &Rule) -> (Vec<u32>, Vec<u32>, u8) {
+        (rule.birth.clone(), rule.survival.clone(), rule.states())
+    }
+
+    #[test]
+    fn parses_b_s_notation() {
+        assert_eq!(counts(&Rule::parse("B3/S23").unwrap()), (vec![3], vec![2, 3], 2));
+    }
+
+    #[test]
+    fn parses_s_b_notation() {
+        assert_eq!(counts(&Rule::parse("23/3").unwrap()), (vec![3], vec![2, 3], 2));
+    }
+
+    #[test]
+    fn parses_lettered_parts_in_either_order() {
+        assert_eq!(counts(&Rule::parse("S23/B3").unwrap()), (vec![3], vec![2, 3], 2));
+        assert_eq!(counts(&Rule::parse("b36/s23").unwrap()), (vec![3, 6], vec![2, 3], 2));
+    }
+
+    #[test]
+    fn parses_generations_rules() {
+        // Brian's Brain.
+        assert_eq!(counts(&Rule::parse("/2/3").unwrap()), (vec![2], vec![], 3));
+        assert_eq!(counts(&Rule::parse("345/2/4").unwrap()), (vec![2], vec![3, 4, 5], 4));
+        assert_eq!(counts(&Rule::parse("B2/S/C3").unwrap()), (vec![2], vec![], 3));
+        assert_eq!(counts(&Rule::parse("B2/S/G3").unwrap()), (vec![2], vec![], 3));
+    }
+
+    #[test]
+    fn parses_comma_lists() {
+        assert_eq!(counts(&Rule::parse("B3/S2,10,11").unwrap()), (vec![3], vec![2, 10, 11], 2));
+        assert_eq!(counts(&Rule::parse("B10,/S").unwrap()), (vec![10], vec![], 2));
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for notation in ["B3/S23", "B2/S/C3", "B3/S2,10,11", "B10,/S", "B/S10,"].iter() {
+            let rule = Rule::parse(notation).unwrap();
+            assert_eq!(rule.to_string(), *notation);
+            assert_eq!(counts(&Rule::parse(&rule.to_string()).unwrap()), counts(&rule));
+        }
+        assert_eq!(Rule::parse("23/3").unwrap().to_string(), "B3/S23");
+        assert_eq!(Rule::parse("345/2/4").unwrap().to_string(), "B2/S345/C4");
+    }
+
+    #[test]
+    fn rejects_bad_rules() {
+        for notation in [
+            "B3", "B3/S23/C4/X", "B3/X23", "B3/B23", "S2/S3",
+            "B3/S2x", "B3/S2,x", "23/3a", "B3/S23/C1", "B3/S23/C256", "B3/S23/Cx",
+        ].iter() {
+            assert!(Rule::parse(notation).is_err(), "'{}' should be rejected", notation);
+        }
+    }
+}
@@ -0,0 +1,240 @@
+// Plain text encoding for cell graphs and world state, one record per line.
+// Pixels are stored as horizontal runs ("x,y,length") to keep files
+// for big images down to a sensible size.
+
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+
+use image::{Image, Color, Point};
+use world::{Cell, Neighbor, Adjacency};
+
+pub fn write_cells<W: Write>(out: &mut W, cells: &[Cell]) -> io::Result<()> {
+    writeln!(out, "cells {}", cells.len())?;
+    for cell in cells.iter() {
+        writeln!(out, "cell {} {} {} {}", cell.color.red, cell.color.green, cell.color.blue, cell.color.alpha)?;
+
+        write!(out, "pixels")?;
+        for &(start, length) in pixel_runs(&cell.pixels).iter() {
+            write!(out, " {},{},{}", start.x, start.y, length)?;
+        }
+        writeln!(out)?;
+
+        write!(out, "neighbors")?;
+        for neighbor in cell.neighbors.iter() {
+            let kind = match neighbor.adjacency {
+                Adjacency::Edge => "e",
+                Adjacency::Corner => "c",
+            };
+            write!(out, " {}:{}:{}", neighbor.cell, kind, neighbor.border)?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+// With `legacy_colors`, colors may leave out alpha as snapshots from before
+// there was any do; cache files are always written with it.
+pub fn read_cells<R: BufRead>(reader: &mut Reader<R>, width: u32, height: u32, legacy_colors: bool) -> Result<Vec<Cell>, String> {
+    let words = reader.expect("cells")?;
+    let count: usize = reader.parse_words(&words, 1)?[0];
+    let mut cells = Vec::with_capacity(count);
+    for _ in 0..count {
+        // Older snapshots have no alpha, and only opaque colors.
+        let mut words = reader.expect("cell")?;
+        if legacy_colors && words.len() == 3 {
+            words.push("255".to_string());
+        }
+        let rgba: Vec<u8> = reader.parse_words(&words, 4)?;
+        let mut cell = Cell{
+            color: Color{ red: rgba[0], green: rgba[1], blue: rgba[2], alpha: rgba[3] },
+            neighbors: Vec::new(),
+            pixels: Vec::new(),
+        };
+
+        for word in reader.expect("pixels")?.iter() {
+            let run: Vec<i32> = reader.parse_list(word, ',', 3)?;
+            let (x, y, length) = (run[0], run[1], run[2]);
+            if x < 0 || y < 0 || length < 0 || x + length > width as i32 || y >= height as i32 {
+                return Err(reader.error(&format!("Pixel run '{}' is outside the image", word)));
+            }
+            for offset in 0..length {
+                cell.pixels.push(Point{ x: x + offset, y: y });
+            }
+        }
+
+        for word in reader.expect("neighbors")?.iter() {
+            let parts: Vec<&str> = word.split(':').collect();
+            if parts.len() != 3 {
+                return Err(reader.error(&format!("Expected 'cell:kind:border', not '{}'", word)));
+            }
+            let adjacency = match parts[1] {
+                "e" => Adjacency::Edge,
+                "c" => Adjacency::Corner,
+                _ => return Err(reader.error(&format!("Unknown adjacency '{}'", parts[1]))),
+            };
+            let neighbor = Neighbor{
+                cell: reader.parse(parts[0])?,
+                adjacency: adjacency,
+                border: reader.parse(parts[2])?,
+            };
+            if neighbor.cell >= count {
+                return Err(reader.error(&format!("Neighbor {} doesn't exist", neighbor.cell)));
+            }
+            cell.neighbors.push(neighbor);
+        }
+
+        cells.push(cell);
+    }
+    Ok(cells)
+}
+
+// Only the non-white pixels, which are all that get drawn.
+pub fn write_boundaries<W: Write>(out: &mut W, boundaries: &Image) -> io::Result<()> {
+    let white = Color{ red: 255, green: 255, blue: 255, alpha: 255 };
+    write!(out, "boundaries")?;
+    for y in 0..boundaries.height as i32 {
+        for x in 0..boundaries.width as i32 {
+            let color = boundaries.color_at(Point{ x: x, y: y });
+            if color != white {
+                write!(out, " {},{},{},{},{},{}", x, y, color.red, color.green, color.blue, color.alpha)?;
+            }
+        }
+    }
+    writeln!(out)
+}
+
+pub fn read_boundaries<R: BufRead>(reader: &mut Reader<R>, width: u32, height: u32, legacy_colors: bool) -> Result<Image, String> {
+    let mut boundaries = Image::white(width, height);
+    for word in reader.expect("boundaries")?.iter() {
+        // Older snapshots have no alpha.
+        let count = if legacy_colors && word.split(',').count() == 5 { 5 } else { 6 };
+        let mut parts: Vec<i32> = reader.parse_list(word, ',', count)?;
+        parts.push(255);
+        let point = Point{ x: parts[0], y: parts[1] };
+        if point.x < 0 || point.y < 0 || point.x >= width as i32 || point.y >= height as i32 {
+            return Err(reader.error(&format!("Boundary pixel '{}' is outside the image", word)));
+        }
+        if parts[2..].iter().any(|channel| *channel < 0 || *channel > 255) {
+            return Err(reader.error(&format!("Boundary color in '{}' is out of range", word)));
+        }
+        let color = Color{ red: parts[2] as u8, green: parts[3] as u8, blue: parts[4] as u8, alpha: parts[5] as u8 };
+        boundaries.set_color_at(point, color);
+    }
+    Ok(boundaries)
+}
+
+// Group pixels into horizontal runs of (start, length).
+fn pixel_runs(pixels: &[Point]) -> Vec<(Point, i32)> {
+    let mut sorted: Vec<Point> = pixels.to_vec();
+    sorted.sort_by(|a, b| (a.y, a.x).cmp(&(b.y, b.x)));
+    let mut runs: Vec<(Point, i32)> = Vec::new();
+    for p in sorted.iter() {
+        if let Some(&mut (start, ref mut length)) = runs.last_mut() {
+            if start.y == p.y && start.x + *length == p.x {
+                *length += 1;
+                continue;
+            }
+        }
+        runs.push((*p, 1));
+    }
+    runs
+}
+
+// Reads records line by line, keeping track of where we are for error messages.
+pub struct Reader<R: BufRead> {
+    lines: io::Lines<R>,
+    line_number: usize,
+}
+
+impl<R: BufRead> Reader<R> {
+    pub fn new(input: R) -> Reader<R> {
+        Reader {
+            lines: input.lines(),
+            line_number: 0,
+        }
+    }
+
+    // Read the next line, which must start with `keyword`, and return the rest of its words.
+    pub fn expect(&mut self, keyword: &str) -> Result<Vec<String>, String> {
+        self.line_number += 1;
+        let line = match self.lines.next() {
+            Some(Ok(line)) => line,
+            Some(Err(e)) => return Err(self.error(&e.to_string())),
+            None => return Err(self.error(&format!("Expected '{}' but found end of file", keyword))),
+        };
+        let mut words = line.split_whitespace();
+        if words.next() != Some(keyword) {
+            return Err(self.error(&format!("Expected '{}'", keyword)));
+        }
+        Ok(words.map(|word| word.to_string()).collect())
+    }
+
+    pub fn parse<T: FromStr>(&self, word: &str) -> Result<T, String> {
+        match word.parse::<T>() {
+            Ok(value) => Ok(value),
+            Err(_) => Err(self.error(&format!("Bad number '{}'", word))),
+        }
+    }
+
+    // Parse exactly `count` whitespace-separated words.
+    pub fn parse_words<T: FromStr>(&self, words: &[String], count: usize) -> Result<Vec<T>, String> {
+        if words.len() != count {
+            return Err(self.error(&format!("Expected {} values", count)));
+        }
+        words.iter().map(|word| self.parse(word)).collect()
+    }
+
+    // Parse exactly `count` values from a single word, e.g. "1,2,3".
+    pub fn parse_list<T: FromStr>(&self, word: &str, separator: char, count: usize) -> Result<Vec<T>, String> {
+        let parts: Vec<&str> = word.split(separator).collect();
+        if parts.len() != count {
+            return Err(self.error(&format!("Expected {} values in '{}'", count, word)));
+        }
+        parts.iter().map(|part| self.parse(part)).collect()
+    }
+
+    pub fn error(&self, message: &str) -> String {
+        format!("line {}: {}", self.line_number, message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn boundaries(text: &str) -> Result<Image, String> {
+        read_boundaries(&mut Reader::new(text.as_bytes()), 4, 4, true)
+    }
+
+    #[test]
+    fn reads_boundary_pixels() {
+        let image = boundaries("boundaries 1,2,0,0,0,255 3,3,10,20,30,128").unwrap();
+        let color = image.color_at(Point{ x: 3, y: 3 });
+        assert_eq!((color.red, color.green, color.blue, color.alpha), (10, 20, 30, 128));
+        assert_eq!(image.color_at(Point{ x: 1, y: 2 }).red, 0);
+        assert_eq!(image.color_at(Point{ x: 0, y: 0 }).red, 255);
+    }
+
+    #[test]
+    fn rejects_out_of_range_boundary_colors() {
+        assert!(boundaries("boundaries 1,2,300,0,0,255").is_err());
+        assert!(boundaries("boundaries 1,2,0,0,0,256").is_err());
+        assert!(boundaries("boundaries 1,2,0,-1,0").is_err());
+        assert!(boundaries("boundaries 4,0,0,0,0,255").is_err());
+    }
+
+    #[test]
+    fn only_legacy_colors_may_leave_out_alpha() {
+        let text = "cells 1\ncell 10 20 30\npixels 0,0,2\nneighbors\nboundaries 2,0,0,0,0\n";
+        let mut reader = Reader::new(text.as_bytes());
+        let cells = read_cells(&mut reader, 4, 4, true).unwrap();
+        assert_eq!(cells[0].color.alpha, 255);
+        assert_eq!(cells[0].pixels.len(), 2);
+        assert_eq!(read_boundaries(&mut reader, 4, 4, true).unwrap().color_at(Point{ x: 2, y: 0 }).alpha, 255);
+
+        let mut reader = Reader::new(text.as_bytes());
+        assert!(read_cells(&mut reader, 4, 4, false).is_err());
+        let mut reader = Reader::new("boundaries 2,0,0,0,0\n".as_bytes());
+        assert!(read_boundaries(&mut reader, 4, 4, false).is_err());
+    }
+}
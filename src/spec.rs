@@ -0,0 +1,393 @@
+extern crate toml;
+
+use std::fs;
+use std::io::Read;
+use std::path;
+
+use image::{Rect, Color};
+use output::Format;
+use render::{Palette, RenderMode};
+use rule::{Rule, Neighborhood, Weighting};
+
+// Everything needed to set up and run a world, so that experiments can be
+// kept as data instead of long command lines. Loaded from a TOML file like:
+//
+//     input = "../examples/hex_grid.png"  # Relative to the spec file.
+//     wrap = true
+//     separators = ["#000000"]             # Colors of lines drawn between cells...
+//     separator_width = 3                  # ...and the thickest to link cells across.
+//     cache = true                         # Reuse cells found in earlier runs.
+//     frames = 200
+//     stop_on_cycle = true                 # Stop early once the world repeats itself.
+//     seed = 1234                          # Omit for a different run every time.
+//
+//     [rule]
+//     notation = "B34/S24"
+//     neighborhood = "all"                 # Or "edge", "corner".
+//     weighting = "count"                  # Or "degree", "border S0.2-0.5/B0.3-0.4".
+//
+//     [initial]
+//     density = 0.3                        # Fraction of cells alive at random.
+//     region = [100, 100, 50, 50]          # Confine them to x, y, width, height...
+//     mask = "soup_mask.png"               # ...or to cells mostly dark here.
+//     image = "pattern.png"                # Or choose exactly which cells start alive.
+//
+//     [render]
+//     mode = "age"                         # Or "state", "activity", "tint".
+//     trail = 5                            # Generations dead cells take to fade.
+//     palette = "dark"                     # Or "classic", "plain", "blueprint", "amber".
+//     alive = "#ffcc00"                    # Override any of the palette's colors;
+//     boundary = "none"                    # "none" leaves cell edges undrawn.
+//
+//     [output]
+//     prefix = "hex"                       # Omit to run in a window.
+//     dir = "hex_out"                      # Where to write; "image_out" if not given.
+//     template = "{input}-{rule}-{frame}.png"  # Also {prefix}, {seed}, {generation}.
+//     start_frame = 100                    # Generations to run before the first frame...
+//     stride = 10                          # ...and between frames after that.
+//     format = "gif"                       # Or "png" for a file per frame, "apng", "y4m".
+//     delay = 50                           # Milliseconds per frame of an animation or video.
+//     loop = 0                             # Times to play it; 0 for forever.
+//     scale = 2                            # Blow frames up by a whole number.
+//     stats = "hex_stats.csv"              # Or .jsonl; relative to where we're run.
+//
+// Anything not given keeps the same default as the command line.
+pub enum Region {
+    Rect(Rect),
+    // Cells mostly dark in this image.
+    Mask(String),
+}
+
+pub struct Spec {
+    pub input: Option<String>,
+    pub wrap: bool,
+    // Pixels of these colors are lines between cells rather than cells.
+    pub separators: Vec<Color>,
+    pub separator_width: u32,
+    // Keep the cells found in each input image on disk for next time.
+    pub cache: bool,
+    pub rule: Rule,
+    // Drives all randomness, so the same seed gives the same run.
+    pub seed: Option<u64>,
+    // Image to take the initial state from, rather than starting at random.
+    pub initial_image: Option<String>,
+    // Chance of each cell starting alive, if not taken from an image.
+    pub density: f64,
+    // Where cells may start alive at random; everywhere if not given.
+    pub region: Option<Region>,
+    pub palette: Palette,
+    pub render_mode: RenderMode,
+    pub trail: u32,
+    pub frames: u32,
+    pub stop_on_cycle: bool,
+    // Output frames are only written if this, the directory or the
+    // template is set; otherwise the world is shown in a window.
+    pub output_prefix: Option<String>,
+    pub output_dir: Option<String>,
+    // File name for output, with placeholders; see `output::FileNames`.
+    pub output_template: Option<String>,
+    // Generations to skip before the first output frame, and between frames.
+    pub start_frame: u64,
+    pub stride: u32,
+    // PNG files if not given, or y4m when writing to standard output.
+    pub format: Option<Format>,
+    // Milliseconds between frames, and times to play, for animated formats.
+    pub delay: u32,
+    pub loop_count: u32,
+    // Output frames are this many times the size of the input image.
+    pub scale: u32,
+    // Per-generation population, births and deaths, as CSV or JSON lines.
+    pub stats_out: Option<String>,
+}
+
+impl Default for Spec {
+    fn default() -> Spec {
+        Spec {
+            input: None,
+            wrap: false,
+            separators: Vec::new(),
+            separator_width: 3,
+            cache: true,
+            rule: Rule::from_ranges(2, 3, 3, 3),
+            seed: None,
+            initial_image: None,
+            density: 0.5,
+            region: None,
+            palette: Palette::default(),
+            render_mode: RenderMode::State,
+            trail: 0,
+            frames: 100,
+            stop_on_cycle: false,
+            output_prefix: None,
+            output_dir: None,
+            output_template: None,
+            start_frame: 0,
+            stride: 1,
+            format: None,
+            delay: 100,
+            loop_count: 0,
+            scale: 1,
+            stats_out: None,
+        }
+    }
+}
+
+impl Spec {
+    pub fn load(path: &path::Path) -> Spec {
+        let mut text = String::new();
+        let res = fs::File::open(path).and_then(|mut file| file.read_to_string(&mut text));
+        match res {
+            Err(e) => panic!("Couldn't read spec file '{}': {}", path.display(), e),
+            _ => {},
+        }
+
+        let mut parser = toml::Parser::new(&text);
+        let table = match parser.parse() {
+            Some(table) => table,
+            None => {
+                let error = &parser.errors[0];
+                let (line, column) = parser.to_linecol(error.lo);
+                panic!(
+                    "Couldn't parse spec file '{}' at line {}, column {}: {}",
+                    path.display(), line + 1, column + 1, error.desc
+                );
+            },
+        };
+        check_keys(&table, &["input", "wrap", "separators", "separator_width", "cache", "seed", "frames", "stop_on_cycle", "rule", "initial", "render", "output"], "spec");
+
+        // Resolve file names relative to the spec file so specs work from any directory.
+        let base_dir = path.parent().unwrap_or(path::Path::new(""));
+
+        let mut spec = Spec::default();
+        if let Some(value) = table.get("input") {
+            spec.input = Some(resolve(base_dir, get_str(value, "input")));
+        }
+        if let Some(value) = table.get("wrap") {
+            spec.wrap = get_bool(value, "wrap");
+        }
+        if let Some(value) = table.get("separators") {
+            spec.separators = match value.as_slice() {
+                Some(values) => values.iter().map(|value| get_color(value, "separators")).collect(),
+                None => panic!("Expected a list of colors for 'separators' in spec file"),
+            };
+        }
+        if let Some(value) = table.get("separator_width") {
+            spec.separator_width = get_u32(value, "separator_width");
+        }
+        if let Some(value) = table.get("cache") {
+            spec.cache = get_bool(value, "cache");
+        }
+        if let Some(value) = table.get("seed") {
+            spec.seed = Some(get_u64(value, "seed"));
+        }
+        if let Some(value) = table.get("frames") {
+            spec.frames = get_u32(value, "frames");
+        }
+        if let Some(value) = table.get("stop_on_cycle") {
+            spec.stop_on_cycle = get_bool(value, "stop_on_cycle");
+        }
+        if let Some(value) = table.get("rule") {
+            spec.load_rule(get_table(value, "rule"));
+        }
+        if let Some(value) = table.get("initial") {
+            spec.load_initial(get_table(value, "initial"), base_dir);
+        }
+        if let Some(value) = table.get("render") {
+            spec.load_render(get_table(value, "render"));
+        }
+        if let Some(value) = table.get("output") {
+            spec.load_output(get_table(value, "output"));
+        }
+        spec
+    }
+
+    fn load_rule(&mut self, table: &toml::Table) {
+        check_keys(table, &["notation", "neighborhood", "weighting"], "rule");
+        if let Some(value) = table.get("notation") {
+            self.rule = match Rule::parse(get_str(value, "rule.notation")) {
+                Ok(rule) => rule,
+                Err(e) => panic!("Bad rule in spec file: {}", e),
+            };
+        }
+        if let Some(value) = table.get("neighborhood") {
+            match Neighborhood::parse(get_str(value, "rule.neighborhood")) {
+                Ok(neighborhood) => self.rule.set_neighborhood(neighborhood),
+                Err(e) => panic!("Bad neighborhood in spec file: {}", e),
+            }
+        }
+        if let Some(value) = table.get("weighting") {
+            match Weighting::parse(get_str(value, "rule.weighting")) {
+                Ok(weighting) => self.rule.set_weighting(weighting),
+                Err(e) => panic!("Bad weighting in spec file: {}", e),
+            }
+        }
+    }
+
+    fn load_initial(&mut self, table: &toml::Table, base_dir: &path::Path) {
+        check_keys(table, &["image", "density", "region", "mask"], "initial");
+        if let Some(value) = table.get("image") {
+            self.initial_image = Some(resolve(base_dir, get_str(value, "initial.image")));
+        }
+        if let Some(value) = table.get("density") {
+            self.density = get_fraction(value, "initial.density");
+        }
+        match (table.get("region"), table.get("mask")) {
+            (Some(_), Some(_)) => panic!("Can't have both 'region' and 'mask' in initial section of spec file"),
+            (Some(value), None) => {
+                let numbers: Vec<i64> = match value.as_slice() {
+                    Some(values) => values.iter().filter_map(|value| value.as_integer()).collect(),
+                    None => Vec::new(),
+                };
+                if numbers.len() != 4 || numbers[2] < 0 || numbers[3] < 0 {
+                    panic!("Expected [x, y, width, height] for 'initial.region' in spec file");
+                }
+                self.region = Some(Region::Rect(Rect{
+                    x: numbers[0] as i32,
+                    y: numbers[1] as i32,
+                    width: numbers[2] as u32,
+                    height: numbers[3] as u32,
+                }));
+            },
+            (None, Some(value)) => {
+                self.region = Some(Region::Mask(resolve(base_dir, get_str(value, "initial.mask"))));
+            },
+            (None, None) => {},
+        }
+    }
+
+    fn load_render(&mut self, table: &toml::Table) {
+        check_keys(table, &["mode", "trail", "palette", "alive", "dead", "boundary"], "render");
+        if let Some(value) = table.get("mode") {
+            self.render_mode = match RenderMode::parse(get_str(value, "render.mode")) {
+                Ok(render_mode) => render_mode,
+                Err(e) => panic!("Bad render mode in spec file: {}", e),
+            };
+        }
+        if let Some(value) = table.get("trail") {
+            self.trail = get_u32(value, "render.trail");
+        }
+        if let Some(value) = table.get("palette") {
+            self.palette = match Palette::parse(get_str(value, "render.palette")) {
+                Ok(palette) => palette,
+                Err(e) => panic!("Bad palette in spec file: {}", e),
+            };
+        }
+        if let Some(value) = table.get("alive") {
+            self.palette.alive = get_color(value, "render.alive");
+        }
+        if let Some(value) = table.get("dead") {
+            self.palette.dead = get_color(value, "render.dead");
+        }
+        if let Some(value) = table.get("boundary") {
+            self.palette.boundary = match Palette::parse_boundary(get_str(value, "render.boundary")) {
+                Ok(boundary) => boundary,
+                Err(e) => panic!("Bad color for 'render.boundary' in spec file: {}", e),
+            };
+        }
+    }
+
+    fn load_output(&mut self, table: &toml::Table) {
+        check_keys(table, &["prefix", "dir", "template", "start_frame", "stride", "format", "delay", "loop", "scale", "stats"], "output");
+        if let Some(value) = table.get("prefix") {
+            self.output_prefix = Some(get_str(value, "output.prefix").to_string());
+        }
+        if let Some(value) = table.get("dir") {
+            self.output_dir = Some(get_str(value, "output.dir").to_string());
+        }
+        if let Some(value) = table.get("template") {
+            self.output_template = Some(get_str(value, "output.template").to_string());
+        }
+        if let Some(value) = table.get("start_frame") {
+            self.start_frame = get_u64(value, "output.start_frame");
+        }
+        if let Some(value) = table.get("stride") {
+            self.stride = match get_u32(value, "output.stride") {
+                0 => panic!("Expected a whole number from 1 up for 'output.stride' in spec file"),
+                stride => stride,
+            };
+        }
+        if let Some(value) = table.get("format") {
+            self.format = match Format::parse(get_str(value, "output.format")) {
+                Ok(format) => Some(format),
+                Err(e) => panic!("Bad output format in spec file: {}", e),
+            };
+        }
+        if let Some(value) = table.get("delay") {
+            self.delay = get_u32(value, "output.delay");
+        }
+        if let Some(value) = table.get("loop") {
+            self.loop_count = get_u32(value, "output.loop");
+        }
+        if let Some(value) = table.get("scale") {
+            self.scale = match get_u32(value, "output.scale") {
+                0 => panic!("Expected a whole number from 1 up for 'output.scale' in spec file"),
+                scale => scale,
+            };
+        }
+        if let Some(value) = table.get("stats") {
+            self.stats_out = Some(get_str(value, "output.stats").to_string());
+        }
+    }
+}
+
+fn resolve(base_dir: &path::Path, file_name: &str) -> String {
+    base_dir.join(file_name).to_string_lossy().into_owned()
+}
+
+// Catch typos rather than silently ignoring them.
+fn check_keys(table: &toml::Table, known_keys: &[&str], section: &str) {
+    for key in table.keys() {
+        if !known_keys.contains(&key.as_str()) {
+            panic!("Unknown key '{}' in {} section of spec file", key, section);
+        }
+    }
+}
+
+fn get_str<'a>(value: &'a toml::Value, key: &str) -> &'a str {
+    match value.as_str() {
+        Some(string) => string,
+        None => panic!("Expected a string for '{}' in spec file", key),
+    }
+}
+
+fn get_bool(value: &toml::Value, key: &str) -> bool {
+    match value.as_bool() {
+        Some(b) => b,
+        None => panic!("Expected true or false for '{}' in spec file", key),
+    }
+}
+
+fn get_u32(value: &toml::Value, key: &str) -> u32 {
+    match value.as_integer() {
+        Some(i) if i >= 0 && i <= u32::max_value() as i64 => i as u32,
+        _ => panic!("Expected an unsigned integer for '{}' in spec file", key),
+    }
+}
+
+fn get_u64(value: &toml::Value, key: &str) -> u64 {
+    match value.as_integer() {
+        Some(i) if i >= 0 => i as u64,
+        _ => panic!("Expected an unsigned integer for '{}' in spec file", key),
+    }
+}
+
+fn get_fraction(value: &toml::Value, key: &str) -> f64 {
+    match value.as_float() {
+        Some(f) if f >= 0.0 && f <= 1.0 => f,
+        _ => panic!("Expected a number from 0.0 to 1.0 for '{}' in spec file", key),
+    }
+}
+
+fn get_color(value: &toml::Value, key: &str) -> Color {
+    match Color::parse(get_str(value, key)) {
+        Ok(color) => color,
+        Err(e) => panic!("Bad color for '{}' in spec file: {}", key, e),
+    }
+}
+
+fn get_table<'a>(value: &'a toml::Value, key: &str) -> &'a toml::Table {
+    match value.as_table() {
+        Some(table) => table,
+        None => panic!("Expected a table for '{}' in spec file", key),
+    }
+}
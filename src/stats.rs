@@ -0,0 +1,70 @@
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path;
+
+use world::Stats;
+
+enum Format {
+    Csv,
+    // One JSON object per line.
+    JsonLines,
+}
+
+// Writes one record per generation, as CSV or JSON lines
+// depending on the file extension.
+pub struct StatsWriter {
+    out: BufWriter<fs::File>,
+    format: Format,
+    file_name: String,
+}
+
+impl StatsWriter {
+    pub fn create(file_name: &str) -> StatsWriter {
+        let path = path::Path::new(file_name);
+        let format = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("csv") => Format::Csv,
+            Some("json") | Some("jsonl") => Format::JsonLines,
+            _ => panic!("Don't know what format to write stats in for '{}'; use .csv or .jsonl", file_name),
+        };
+        let file = match fs::File::create(path) {
+            Ok(file) => file,
+            Err(e) => panic!("Couldn't create stats file '{}': {}", file_name, e),
+        };
+        let mut writer = StatsWriter {
+            out: BufWriter::new(file),
+            format: format,
+            file_name: file_name.to_string(),
+        };
+        if let Format::Csv = writer.format {
+            let res = writeln!(writer.out, "generation,population,births,deaths");
+            writer.check(res);
+        }
+        writer
+    }
+
+    pub fn write(&mut self, stats: &Stats) {
+        let res = match self.format {
+            Format::Csv => writeln!(
+                self.out, "{},{},{},{}",
+                stats.generation, stats.population, stats.births, stats.deaths
+            ),
+            Format::JsonLines => writeln!(
+                self.out, "{{\"generation\":{},\"population\":{},\"births\":{},\"deaths\":{}}}",
+                stats.generation, stats.population, stats.births, stats.deaths
+            ),
+        };
+        self.check(res);
+    }
+
+    pub fn flush(&mut self) {
+        let res = self.out.flush();
+        self.check(res);
+    }
+
+    fn check<T, E: ::std::fmt::Display>(&self, res: Result<T, E>) {
+        match res {
+            Err(e) => panic!("Couldn't write stats to '{}': {}", self.file_name, e),
+            _ => {},
+        }
+    }
+}
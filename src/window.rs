@@ -8,39 +8,205 @@ use self::sdl2::{
     render,
     keycode,
     event,
+    mouse,
     timer,
     pixels,
     rect,
 };
 
-use world;
+use world::{self, Adjacency};
+use image::Point;
+use rule::{DEAD, ALIVE};
+use stats::StatsWriter;
+
+// Generations per second to choose from with +/-.
+const RATES: [u32; 9] = [1, 2, 5, 10, 20, 50, 100, 200, 500];
+const DEFAULT_RATE_INDEX: usize = 4;
+// Most generations to step between redraws, so a world too big to keep up
+// with the rate still gets drawn and answers key presses.
+const MAX_STEPS_PER_PASS: u64 = 20;
+
+// Bounds on the initial window size; images outside them start zoomed.
+const MIN_WINDOW_SIZE: f32 = 400.0;
+const MAX_WINDOW_WIDTH: f32 = 1200.0;
+const MAX_WINDOW_HEIGHT: f32 = 900.0;
+const MAX_ZOOM: f32 = 64.0;
+const WHEEL_ZOOM_FACTOR: f32 = 1.25;
+
+// Which part of the world is shown in the window, and how big.
+struct View {
+    image_width: f32,
+    image_height: f32,
+    wrap: bool,
+    window_width: f32,
+    window_height: f32,
+    // Window pixels per image pixel.
+    zoom: f32,
+    // Image coordinates of the window's top left corner.
+    x: f32,
+    y: f32,
+    // Keep the whole image in view as the window is resized.
+    fit: bool,
+}
+
+impl View {
+    fn new(image_width: u32, image_height: u32, wrap: bool) -> View {
+        // Scale tiny tilings up by a whole number, and huge ones down to fit the screen.
+        let (width, height) = (image_width as f32, image_height as f32);
+        let zoom = if width > MAX_WINDOW_WIDTH || height > MAX_WINDOW_HEIGHT {
+            (MAX_WINDOW_WIDTH / width).min(MAX_WINDOW_HEIGHT / height)
+        } else {
+            (MIN_WINDOW_SIZE / width.max(height)).floor().max(1.0)
+        };
+        View {
+            image_width: width,
+            image_height: height,
+            wrap: wrap,
+            window_width: (width * zoom).round(),
+            window_height: (height * zoom).round(),
+            zoom: zoom,
+            x: 0.0,
+            y: 0.0,
+            fit: false,
+        }
+    }
+
+    fn resize(&mut self, window_width: i32, window_height: i32) {
+        self.window_width = window_width.max(1) as f32;
+        self.window_height = window_height.max(1) as f32;
+        if self.fit {
+            self.fit_to_window();
+        } else {
+            self.constrain();
+        }
+    }
+
+    fn fit_to_window(&mut self) {
+        self.fit = true;
+        self.zoom = (self.window_width / self.image_width).min(self.window_height / self.image_height);
+        self.constrain();
+    }
+
+    // Zoom in or out, keeping the given window position over the same part of the image.
+    fn zoom_at(&mut self, zoom: f32, window_x: f32, window_y: f32) {
+        self.fit = false;
+        let image_x = self.x + window_x / self.zoom;
+        let image_y = self.y + window_y / self.zoom;
+        self.zoom = zoom.max(self.min_zoom()).min(MAX_ZOOM);
+        self.x = image_x - window_x / self.zoom;
+        self.y = image_y - window_y / self.zoom;
+        self.constrain();
+    }
+
+    fn zoom_at_center(&mut self, zoom: f32) {
+        let (center_x, center_y) = (self.window_width / 2.0, self.window_height / 2.0);
+        self.zoom_at(zoom, center_x, center_y);
+    }
+
+    // Move the view by a distance in window pixels.
+    fn pan(&mut self, dx: f32, dy: f32) {
+        self.fit = false;
+        self.x += dx / self.zoom;
+        self.y += dy / self.zoom;
+        self.constrain();
+    }
+
+    // Don't let the image get too small to see, or to tile sensibly.
+    fn min_zoom(&self) -> f32 {
+        (self.window_width / self.image_width).min(self.window_height / self.image_height).min(1.0) / 4.0
+    }
+
+    // Panning goes round and round a toroidal world; otherwise the image stays
+    // in view, centered if it's smaller than the window.
+    fn constrain(&mut self) {
+        let visible_width = self.window_width / self.zoom;
+        let visible_height = self.window_height / self.zoom;
+        if self.wrap {
+            self.x = ((self.x % self.image_width) + self.image_width) % self.image_width;
+            self.y = ((self.y % self.image_height) + self.image_height) % self.image_height;
+        } else {
+            self.x = constrain_axis(self.x, self.image_width, visible_width);
+            self.y = constrain_axis(self.y, self.image_height, visible_height);
+        }
+    }
+
+    // The image pixel under a window position.
+    fn to_image(&self, window_x: i32, window_y: i32) -> Point {
+        let mut x = (self.x + window_x as f32 / self.zoom).floor() as i32;
+        let mut y = (self.y + window_y as f32 / self.zoom).floor() as i32;
+        if self.wrap {
+            let (width, height) = (self.image_width as i32, self.image_height as i32);
+            x = ((x % width) + width) % width;
+            y = ((y % height) + height) % height;
+        }
+        Point{ x: x, y: y }
+    }
+
+    // Where to draw the image: once, or tiled across the whole window if the world wraps.
+    fn destinations(&self) -> Vec<rect::Rect> {
+        let tile_width = self.image_width * self.zoom;
+        let tile_height = self.image_height * self.zoom;
+        let first_x = -self.x * self.zoom;
+        let first_y = -self.y * self.zoom;
+        let (columns, rows) = if self.wrap {
+            (((self.window_width - first_x) / tile_width).ceil() as i32,
+             ((self.window_height - first_y) / tile_height).ceil() as i32)
+        } else {
+            (1, 1)
+        };
+
+        let mut destinations = Vec::new();
+        for row in 0..rows {
+            for column in 0..columns {
+                // Round the edges rather than the sizes so tiles meet without gaps.
+                let left = (first_x + column as f32 * tile_width).round();
+                let top = (first_y + row as f32 * tile_height).round();
+                let right = (first_x + (column + 1) as f32 * tile_width).round();
+                let bottom = (first_y + (row + 1) as f32 * tile_height).round();
+                destinations.push(rect::Rect::new(left as i32, top as i32, (right - left) as i32, (bottom - top) as i32));
+            }
+        }
+        destinations
+    }
+}
+
+fn constrain_axis(position: f32, image_size: f32, visible_size: f32) -> f32 {
+    if visible_size >= image_size {
+        (image_size - visible_size) / 2.0
+    } else {
+        position.max(0.0).min(image_size - visible_size)
+    }
+}
 
 pub struct Window {
     pub width: u32,
     pub height: u32,
     pub world: world::World,
+    stats_out: Option<StatsWriter>,
 }
 
 impl Window {
-    pub fn new(world: world::World) -> Window {
+    pub fn new(world: world::World, stats_out: Option<StatsWriter>) -> Window {
         Window {
             width: world.image().width,
             height: world.image().height,
             world: world,
+            stats_out: stats_out,
         }
     }
 
     pub fn run(&mut self) {
         let sdl_context = sdl2::init(sdl2::INIT_VIDEO).unwrap();
 
+        let mut view = View::new(self.width, self.height, self.world.wrap());
         let window = video::Window::new(
             &sdl_context,
             "lifelike",
             video::WindowPos::PosCentered,
             video::WindowPos::PosCentered,
-            self.width as i32,
-            self.height as i32,
-            video::OPENGL
+            view.window_width as i32,
+            view.window_height as i32,
+            video::OPENGL | video::RESIZABLE
         ).unwrap();
 
         let mut renderer = render::Renderer::from_window(
@@ -51,36 +217,269 @@ impl Window {
 
         let mut texture = renderer.create_texture_streaming(pixels::PixelFormatEnum::ABGR8888, (self.width as i32, self.height as i32)).unwrap();
 
-        let mut drawer = renderer.drawer();
-        drawer.set_draw_color(sdl2::pixels::Color::RGB(0, 0, 0));
-        drawer.clear();
-        drawer.present();
+        {
+            let mut drawer = renderer.drawer();
+            drawer.set_draw_color(sdl2::pixels::Color::RGB(0, 0, 0));
+            drawer.clear();
+            drawer.present();
+        }
+
+        println!("Space: pause/resume. N or '.': step once while paused. +/-: change speed. Esc: quit.");
+        println!("Click a cell to toggle it; drag to paint more cells the same way. I: describe the cell under the pointer.");
+        println!("Mouse wheel: zoom. Right-drag or arrow keys: pan. F: fit to window. 1: actual size.");
 
         let mut running = true;
+        let mut paused = false;
+        let mut rate_index = DEFAULT_RATE_INDEX;
+        let mut last_step_ticks = timer::get_ticks();
+        // Generations actually stepped per second, measured over about a second,
+        // since big worlds can't always keep up.
+        let mut measured_rate: Option<u32> = None;
+        let mut measure_start_ticks = last_step_ticks;
+        let mut measured_steps = 0u32;
+        // Whether the world, the view of it, or the title need redrawing.
+        let mut world_changed = true;
+        let mut view_changed = true;
+        let mut title_changed = true;
+        let mut cycle_reported = self.world.cycle().is_some();
+        // State being painted while the left mouse button is held down.
+        let mut painting: Option<u8> = None;
+        let mut mouse_position = (0, 0);
+        let mut panning = false;
         let mut event_pump = sdl_context.event_pump();
 
+        if let Some(ref mut stats_out) = self.stats_out {
+            stats_out.write(&self.world.stats());
+        }
+
         while running {
+            let mut step_once = false;
+            let mut edited = false;
+            let mut rate_changed = false;
             for event in event_pump.poll_iter() {
                 match event {
                     event::Event::Quit {..} | event::Event::KeyDown { keycode: keycode::KeyCode::Escape, .. } => {
                         running = false
                     },
+                    event::Event::KeyDown { keycode: keycode::KeyCode::Space, .. } => {
+                        paused = !paused;
+                        rate_changed = true;
+                    },
+                    event::Event::KeyDown { keycode: keycode::KeyCode::N, .. } |
+                    event::Event::KeyDown { keycode: keycode::KeyCode::Period, .. } => {
+                        if paused {
+                            step_once = true;
+                        }
+                    },
+                    event::Event::KeyDown { keycode: keycode::KeyCode::Plus, .. } |
+                    event::Event::KeyDown { keycode: keycode::KeyCode::Equals, .. } |
+                    event::Event::KeyDown { keycode: keycode::KeyCode::KpPlus, .. } => {
+                        if rate_index + 1 < RATES.len() {
+                            rate_index += 1;
+                            rate_changed = true;
+                        }
+                    },
+                    event::Event::KeyDown { keycode: keycode::KeyCode::Minus, .. } |
+                    event::Event::KeyDown { keycode: keycode::KeyCode::KpMinus, .. } => {
+                        if rate_index > 0 {
+                            rate_index -= 1;
+                            rate_changed = true;
+                        }
+                    },
+                    event::Event::KeyDown { keycode: keycode::KeyCode::F, .. } => {
+                        view.fit_to_window();
+                        view_changed = true;
+                    },
+                    event::Event::KeyDown { keycode: keycode::KeyCode::Num1, .. } => {
+                        view.zoom_at_center(1.0);
+                        view_changed = true;
+                    },
+                    event::Event::KeyDown { keycode: keycode::KeyCode::Left, .. } => {
+                        view.pan(-view.window_width / 10.0, 0.0);
+                        view_changed = true;
+                    },
+                    event::Event::KeyDown { keycode: keycode::KeyCode::Right, .. } => {
+                        view.pan(view.window_width / 10.0, 0.0);
+                        view_changed = true;
+                    },
+                    event::Event::KeyDown { keycode: keycode::KeyCode::Up, .. } => {
+                        view.pan(0.0, -view.window_height / 10.0);
+                        view_changed = true;
+                    },
+                    event::Event::KeyDown { keycode: keycode::KeyCode::Down, .. } => {
+                        view.pan(0.0, view.window_height / 10.0);
+                        view_changed = true;
+                    },
+                    event::Event::MouseWheel { y, .. } => {
+                        let zoom = view.zoom * WHEEL_ZOOM_FACTOR.powi(y);
+                        view.zoom_at(zoom, mouse_position.0 as f32, mouse_position.1 as f32);
+                        view_changed = true;
+                    },
+                    event::Event::Window { win_event_id: event::WindowEventId::Resized, data1, data2, .. } => {
+                        view.resize(data1, data2);
+                        view_changed = true;
+                    },
+                    event::Event::MouseButtonDown { mouse_btn: mouse::Mouse::Right, .. } |
+                    event::Event::MouseButtonDown { mouse_btn: mouse::Mouse::Middle, .. } => {
+                        panning = true;
+                    },
+                    event::Event::MouseButtonUp { mouse_btn: mouse::Mouse::Right, .. } |
+                    event::Event::MouseButtonUp { mouse_btn: mouse::Mouse::Middle, .. } => {
+                        panning = false;
+                    },
+                    event::Event::MouseButtonDown { mouse_btn: mouse::Mouse::Left, x, y, .. } => {
+                        if let Some(cell) = self.world.cell_at(view.to_image(x, y)) {
+                            // Toggle the clicked cell, then keep painting that state while dragging.
+                            let state = if self.world.cell_state(cell) == ALIVE { DEAD } else { ALIVE };
+                            self.world.set_cell_state(cell, state);
+                            painting = Some(state);
+                            edited = true;
+                        }
+                    },
+                    event::Event::KeyDown { keycode: keycode::KeyCode::I, .. } => {
+                        match self.world.cell_at(view.to_image(mouse_position.0, mouse_position.1)) {
+                            Some(cell) => self.describe_cell(cell),
+                            None => println!("No cell under the pointer."),
+                        }
+                    },
+                    event::Event::MouseMotion { x, y, xrel, yrel, .. } => {
+                        mouse_position = (x, y);
+                        if panning {
+                            // Drag the image along with the pointer.
+                            view.pan(-xrel as f32, -yrel as f32);
+                            view_changed = true;
+                        }
+                        if let Some(state) = painting {
+                            if let Some(cell) = self.world.cell_at(view.to_image(x, y)) {
+                                if self.world.cell_state(cell) != state {
+                                    self.world.set_cell_state(cell, state);
+                                    edited = true;
+                                }
+                            }
+                        }
+                    },
+                    event::Event::MouseButtonUp { mouse_btn: mouse::Mouse::Left, .. } => {
+                        painting = None;
+                    },
                     _ => {}
                 }
             }
+            if edited {
+                world_changed = true;
+                // Editing may have broken a cycle we'd already reported.
+                cycle_reported = self.world.cycle().is_some();
+            }
 
-            // Render and display the most recently calculated frame.
-            self.world.update_world_image();
-            texture.update(None, &self.world.image().pixel_data, 4 * self.width as i32).unwrap();
-            drawer.copy(&texture, None, Some(rect::Rect::new(0, 0, self.width as i32, self.height as i32)));
-            // drawer.copy_ex(&texture, None, Some(rect::Rect::new(450, 100, 256, 256)), 30.0, None, (false, false));
-            drawer.present();
+            // Step the world as many times as are due, or once when asked to while paused.
+            let ticks = timer::get_ticks();
+            if rate_changed {
+                // Start timing afresh, rather than making up for time spent paused.
+                last_step_ticks = ticks;
+                measured_rate = None;
+                measure_start_ticks = ticks;
+                measured_steps = 0;
+                title_changed = true;
+            }
+            let rate = RATES[rate_index] as u64;
+            let steps = if paused {
+                if step_once { 1 } else { 0 }
+            } else {
+                let due = ticks.wrapping_sub(last_step_ticks) as u64 * rate / 1000;
+                if due > MAX_STEPS_PER_PASS {
+                    // Too far behind to catch up; carry on from now.
+                    last_step_ticks = ticks;
+                    MAX_STEPS_PER_PASS
+                } else {
+                    // Keep the time towards the next step.
+                    last_step_ticks = last_step_ticks.wrapping_add((due * 1000 / rate) as u32);
+                    due
+                }
+            };
+
+            if !paused {
+                measured_steps += steps as u32;
+                let elapsed = ticks.wrapping_sub(measure_start_ticks);
+                if elapsed >= 1000 {
+                    measured_rate = Some((measured_steps as u64 * 1000 / elapsed as u64) as u32);
+                    measure_start_ticks = ticks;
+                    measured_steps = 0;
+                    title_changed = true;
+                }
+            }
+
+            for _ in 0..steps {
+                self.world.step();
+                world_changed = true;
+                title_changed = true;
+                if let Some(ref mut stats_out) = self.stats_out {
+                    stats_out.write(&self.world.stats());
+                }
+                if !cycle_reported {
+                    if let Some(cycle) = self.world.cycle() {
+                        println!("Reached a cycle: {}.", cycle);
+                        cycle_reported = true;
+                    }
+                }
+            }
+
+            if world_changed {
+                // Render the most recently calculated frame.
+                self.world.update_world_image();
+                texture.update(None, &self.world.image().pixel_data, 4 * self.width as i32).unwrap();
+            }
+            if world_changed || view_changed {
+                let mut drawer = renderer.drawer();
+                drawer.clear();
+                for destination in view.destinations().into_iter() {
+                    drawer.copy(&texture, None, Some(destination));
+                }
+                // drawer.copy_ex(&texture, None, Some(rect::Rect::new(450, 100, 256, 256)), 30.0, None, (false, false));
+                drawer.present();
+                if view_changed {
+                    title_changed = true;
+                }
+                world_changed = false;
+                view_changed = false;
+            }
+
+            if title_changed {
+                let speed = match measured_rate {
+                    _ if paused => "paused".to_string(),
+                    // Own up when the world can't keep up with the chosen rate.
+                    Some(actual) if actual * 10 < RATES[rate_index] * 9 => {
+                        format!("{} generations/s (set to {})", actual, RATES[rate_index])
+                    },
+                    _ => format!("{} generations/s", RATES[rate_index]),
+                };
+                let title = format!(
+                    "lifelike - generation {} - {} - zoom {}%",
+                    self.world.stats().generation, speed, (view.zoom * 100.0).round()
+                );
+                if let Some(mut properties) = renderer.window_properties(&event_pump) {
+                    properties.set_title(&title);
+                }
+                title_changed = false;
+            }
 
-            // Step the world.
-            self.world.step();
+            // Short delay so we notice key presses promptly without spinning.
+            timer::delay(5);
+        }
 
-            // TODO: Delay by a minimum of S since start of frame--not a fixed amount.
-            timer::delay(50);
+        if let Some(ref mut stats_out) = self.stats_out {
+            stats_out.flush();
         }
     }
+
+    fn describe_cell(&self, cell: usize) {
+        let neighbors = self.world.cell_neighbors(cell);
+        let edge_neighbors = neighbors.iter().filter(|neighbor| neighbor.adjacency == Adjacency::Edge).count();
+        let color = self.world.cell_color(cell);
+        println!(
+            "Cell {} of {}: state {}, age {}, {} births and deaths, {} pixels, color #{:02x}{:02x}{:02x}, {} neighbors ({} edge, {} corner).",
+            cell, self.world.cell_count(), self.world.cell_state(cell), self.world.cell_age(cell),
+            self.world.cell_flips(cell), self.world.cell_pixel_count(cell),
+            color.red, color.green, color.blue,
+            neighbors.len(), edge_neighbors, neighbors.len() - edge_neighbors
+        );
+    }
 }
@@ -1,65 +1,156 @@
 extern crate png;
 extern crate rand;
 
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::iter::repeat;
 use std::mem;
+use std::path;
 
 use image::{Image, Color, Point};
+use render::{self, Palette, RenderMode};
+use rule::{Rule, Neighborhood, Weighting, DEAD, ALIVE};
+use snapshot;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Adjacency {
+    // The cells share at least one pixel edge.
+    Edge,
+    // The cells only touch diagonally, at pixel corners.
+    Corner,
+}
+
+pub struct Neighbor {
+    pub cell: usize,
+    pub adjacency: Adjacency,
+    // Number of pixel edges the two cells share; zero for corner neighbors.
+    pub border: u32,
+}
 
 pub struct Cell {
     pub color: Color,
-    pub neighbors: Vec<usize>,
+    pub neighbors: Vec<Neighbor>,
     pub pixels: Vec<Point>,
 }
 
+// What happened in the most recent generation.
+#[derive(Copy, Clone)]
+pub struct Stats {
+    pub generation: u64,
+    // Living cells, not counting dying ones.
+    pub population: usize,
+    pub births: usize,
+    pub deaths: usize,
+}
+
+// A state that has been seen before, so the world will repeat forever.
+#[derive(Copy, Clone)]
+pub struct Cycle {
+    // Generations before the repeating states began.
+    pub transient: u64,
+    // 1 for a still life.
+    pub period: u64,
+}
+
+impl fmt::Display for Cycle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.period == 1 {
+            write!(f, "still life after a transient of {} generations", self.transient)
+        } else {
+            write!(f, "period {} after a transient of {} generations", self.period, self.transient)
+        }
+    }
+}
+
 pub struct World {
     cells: Vec<Cell>,
-    front: Vec<bool>,
-    back: Vec<bool>,
+    // Cell states; see `rule` for what the values mean.
+    front: Vec<u8>,
+    back: Vec<u8>,
     image: Image,
     cell_boundaries: Image,
-    smin: u32,
-    smax: u32,
-    rmin: u32,
-    rmax: u32,
-    proportional: bool,
+    // Which cell each pixel belongs to.
+    cell_map: Vec<Option<usize>>,
+    // Whether opposite edges of the image meet.
+    wrap: bool,
+    rule: Rule,
+    stats: Stats,
+    // Generation at which each state was first seen, by hash.
+    history: HashMap<u64, u64>,
+    cycle: Option<Cycle>,
+    // For coloring cells by their history, which isn't kept in snapshots.
+    // Generations each cell has been alive in a row, or 0 if it isn't.
+    ages: Vec<u32>,
+    // Births and deaths of each cell over `activity_generations`.
+    flips: Vec<u32>,
+    activity_generations: u64,
+    // Generations since each cell stopped being alive, if it has.
+    since_death: Vec<Option<u32>>,
+    palette: Palette,
+    render_mode: RenderMode,
+    // How many generations dead cells leave a fading trail for.
+    trail: u32,
 }
 
 impl World {
     pub fn new(
-        front: Vec<bool>,
-        back: Vec<bool>,
+        front: Vec<u8>,
+        back: Vec<u8>,
         image: Image,
         cell_boundaries: Image,
-        smin: u32,
-        smax: u32,
-        rmin: u32,
-        rmax: u32,
-        proportional: bool,
+        cell_map: Vec<Option<usize>>,
+        wrap: bool,
+        rule: Rule,
         cells: Vec<Cell>,
     ) -> World {
-        World {
+        let population = front.iter().filter(|state| **state == ALIVE).count();
+        let ages = front.iter().map(|state| if *state == ALIVE { 1 } else { 0 }).collect();
+        let cell_count = cells.len();
+        let mut world = World {
             cells: cells,
             front: front,
             back: back,
             image: image,
             cell_boundaries: cell_boundaries,
-            smin: smin,
-            smax: smax,
-            rmin: rmin,
-            rmax: rmax,
-            proportional: proportional,
-        }
+            cell_map: cell_map,
+            wrap: wrap,
+            rule: rule,
+            stats: Stats{ generation: 0, population: population, births: 0, deaths: 0 },
+            history: HashMap::new(),
+            cycle: None,
+            ages: ages,
+            flips: vec![0; cell_count],
+            activity_generations: 0,
+            since_death: vec![None; cell_count],
+            palette: Palette::default(),
+            render_mode: RenderMode::State,
+            trail: 0,
+        };
+        world.record_history();
+        world
     }
 
     pub fn update_world_image(&mut self) {
         // Write out current state.
         for (i, cell) in self.cells.iter().enumerate() {
+            let state = (*self.front)[i];
+            let color = match self.render_mode {
+                RenderMode::Activity => render::activity_color(self.flips[i], self.activity_generations),
+                RenderMode::Age if state == ALIVE => render::age_color(self.ages[i]),
+                RenderMode::Tint => self.palette.tint_color(cell.color, state, self.rule.states()),
+                _ => {
+                    let trail_color = match self.since_death[i] {
+                        Some(since_death) if state == DEAD => self.palette.trail_color(since_death, self.trail),
+                        _ => None,
+                    };
+                    trail_color.unwrap_or(self.palette.state_color(state, self.rule.states()))
+                },
+            };
             for p in cell.pixels.iter() {
-                let color = if (*self.front)[i] {
-                    Color{ red: 63, green: 63, blue: 63 }
-                } else {
-                    Color{ red: 255, green: 255, blue: 255 }
-                };
                 self.image.set_color_at(*p, color);
             }
         }
@@ -68,45 +159,337 @@ impl World {
             for x in 0..self.image.width as i32 {
                 let p = Point{ x: x, y: y };
                 let color_in_boundary_image = self.cell_boundaries.color_at(p);
-                let white = Color{ red: 255, green: 255, blue: 255 };
+                let white = Color{ red: 255, green: 255, blue: 255, alpha: 255 };
                 if color_in_boundary_image != white {
-                    self.image.set_color_at(p, color_in_boundary_image);
+                    match self.palette.boundary {
+                        Some(boundary_color) => self.image.set_color_at(p, boundary_color),
+                        // Separator lines belong to no cell, so they still need drawing.
+                        None if self.cell_map[self.image.linear_index(p)].is_none() => {
+                            self.image.set_color_at(p, color_in_boundary_image);
+                        },
+                        None => {},
+                    }
                 }
             }
         }
     }
 
     pub fn step(&mut self) {
+        let mut stats = Stats{ generation: self.stats.generation + 1, population: 0, births: 0, deaths: 0 };
+
         // Calculate next frame.
         for i in 0..self.cells.len() {
-            let alive = (*self.front)[i];
-            let mut living_neighbors = 0u32;
-            for neighbor in self.cells[i].neighbors.iter() {
-                if (*self.front)[*neighbor] {
-                    if self.proportional {
-                        living_neighbors += self.cells[*neighbor].neighbors.len() as u32;
-                    } else {
-                        living_neighbors += 1;
-                    }
-                }
-            }
-            if self.proportional {
-                living_neighbors = living_neighbors * 4 / self.cells[i].neighbors.len() as u32;
-            }
+            let state = (*self.front)[i];
+            let (survives, born) = match self.rule.weighting() {
+                Weighting::Border(thresholds) => {
+                    let fraction = self.living_border_fraction(i);
+                    (thresholds.survives(fraction), thresholds.born(fraction))
+                },
+                weighting => {
+                    let living_neighbors = self.living_neighbor_count(i, weighting == Weighting::Degree);
+                    (self.rule.survives(living_neighbors), self.rule.born(living_neighbors))
+                },
+            };
 
             // Apply life rules.
-            self.back[i] = if alive {
-                living_neighbors >= self.smin && // Sufficient neighbors to sustain.
-                living_neighbors <= self.smax // Not so many we're overcrowded.
+            let next_state = self.rule.next_state(state, survives, born);
+            self.back[i] = next_state;
+
+            if next_state == ALIVE {
+                stats.population += 1;
+                if state != ALIVE {
+                    stats.births += 1;
+                    self.flips[i] += 1;
+                    self.ages[i] = 0;
+                }
+                self.ages[i] += 1;
+                self.since_death[i] = None;
             } else {
-                living_neighbors >= self.rmin && // Sufficient neighbors to reproduce.
-                living_neighbors <= self.rmax // Not so many we're overcrowded;
-                                              // possible to make higher than smax to
-                                              // give unfair advantage to newborns.
+                if state == ALIVE {
+                    stats.deaths += 1;
+                    self.flips[i] += 1;
+                    self.since_death[i] = Some(0);
+                } else {
+                    self.since_death[i] = self.since_death[i].map(|since_death| since_death.saturating_add(1));
+                }
+                self.ages[i] = 0;
             }
         }
 
         mem::swap(&mut self.front, &mut self.back);
+        self.activity_generations += 1;
+        self.stats = stats;
+        self.record_history();
+    }
+
+    fn record_history(&mut self) {
+        // Once we know the cycle there's nothing more to learn.
+        if self.cycle.is_some() {
+            return;
+        }
+        let mut hasher = DefaultHasher::new();
+        self.front.hash(&mut hasher);
+        let generation = self.stats.generation;
+        match self.history.insert(hasher.finish(), generation) {
+            Some(first_seen) => {
+                self.cycle = Some(Cycle{ transient: first_seen, period: generation - first_seen });
+                self.history.clear();
+            },
+            None => {},
+        }
+    }
+
+    // Cells are identified by index, from 0 up to `cell_count()`.
+    pub fn cell_count(&self) -> usize {
+        self.cells.len()
+    }
+
+    // The cell a pixel belongs to, if any.
+    pub fn cell_at(&self, point: Point) -> Option<usize> {
+        let in_bounds = point.x >= 0 && point.y >= 0 &&
+            point.x < self.image.width as i32 && point.y < self.image.height as i32;
+        if in_bounds {
+            self.cell_map[self.image.linear_index(point)]
+        } else {
+            None
+        }
+    }
+
+    pub fn cell_state(&self, cell: usize) -> u8 {
+        self.front[cell]
+    }
+
+    pub fn cell_neighbors(&self, cell: usize) -> &[Neighbor] {
+        &self.cells[cell].neighbors
+    }
+
+    pub fn cell_pixel_count(&self, cell: usize) -> usize {
+        self.cells[cell].pixels.len()
+    }
+
+    // Color of the cell in the input image.
+    pub fn cell_color(&self, cell: usize) -> Color {
+        self.cells[cell].color
+    }
+
+    // For editing the world by hand. Previous states no longer lead
+    // here, so any cycle found so far is forgotten.
+    pub fn set_cell_state(&mut self, cell: usize, state: u8) {
+        if self.front[cell] == state {
+            return;
+        }
+        if self.front[cell] == ALIVE {
+            self.stats.population -= 1;
+        } else if state == ALIVE {
+            self.stats.population += 1;
+        }
+        self.front[cell] = state;
+        self.ages[cell] = if state == ALIVE { 1 } else { 0 };
+        self.history.clear();
+        self.cycle = None;
+        self.record_history();
+    }
+
+    // Carry on under a different rule. States it doesn't have become dead,
+    // and any cycle found so far is forgotten.
+    pub fn set_rule(&mut self, rule: Rule) {
+        for state in self.front.iter_mut() {
+            if *state >= rule.states() {
+                *state = DEAD;
+            }
+        }
+        self.rule = rule;
+        self.history.clear();
+        self.cycle = None;
+        self.record_history();
+    }
+
+    // Set once the current state has been seen before. Only states since
+    // the world was created (or resumed from a snapshot) are remembered.
+    pub fn cycle(&self) -> Option<Cycle> {
+        self.cycle
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    fn living_neighbor_count(&self, cell: usize, proportional: bool) -> u32 {
+        let neighborhood = self.rule.neighborhood();
+        let mut living_neighbors = 0u32;
+        for neighbor in self.cells[cell].neighbors.iter() {
+            if !neighborhood.includes(neighbor.adjacency) {
+                continue;
+            }
+            // Dying cells don't count as living neighbors.
+            if (*self.front)[neighbor.cell] == ALIVE {
+                if proportional {
+                    living_neighbors += self.degree(neighbor.cell);
+                } else {
+                    living_neighbors += 1;
+                }
+            }
+        }
+        if proportional {
+            let degree = self.degree(cell);
+            if degree > 0 {
+                living_neighbors = living_neighbors * 4 / degree;
+            }
+        }
+        living_neighbors
+    }
+
+    // Fraction of the cell's shared border that it shares with living neighbors.
+    pub fn living_border_fraction(&self, cell: usize) -> f32 {
+        let neighborhood = self.rule.neighborhood();
+        let mut living_border = 0u32;
+        let mut total_border = 0u32;
+        for neighbor in self.cells[cell].neighbors.iter() {
+            if !neighborhood.includes(neighbor.adjacency) {
+                continue;
+            }
+            total_border += neighbor.border;
+            if (*self.front)[neighbor.cell] == ALIVE {
+                living_border += neighbor.border;
+            }
+        }
+        if total_border == 0 {
+            0.0
+        } else {
+            living_border as f32 / total_border as f32
+        }
+    }
+
+    // Number of neighbors the rule counts for the given cell.
+    fn degree(&self, cell: usize) -> u32 {
+        let neighborhood = self.rule.neighborhood();
+        self.cells[cell].neighbors.iter().filter(|neighbor| neighborhood.includes(neighbor.adjacency)).count() as u32
+    }
+
+    // Write everything needed to carry on from where we are.
+    pub fn save_snapshot(&self, path: &path::Path) {
+        let res = fs::File::create(path).and_then(|file| {
+            let mut out = BufWriter::new(file);
+            self.write_snapshot(&mut out)?;
+            out.flush()
+        });
+        match res {
+            Err(e) => panic!("Couldn't write snapshot '{}': {}", path.display(), e),
+            _ => {},
+        }
+    }
+
+    fn write_snapshot<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "lifelike-snapshot 1")?;
+        writeln!(out, "size {} {}", self.image.width, self.image.height)?;
+        writeln!(out, "wrap {}", self.wrap as u8)?;
+        writeln!(out, "generation {}", self.stats.generation)?;
+        writeln!(out, "rule {}", self.rule)?;
+        writeln!(out, "neighborhood {}", self.rule.neighborhood())?;
+        writeln!(out, "weighting {}", self.rule.weighting())?;
+        snapshot::write_cells(out, &self.cells)?;
+        snapshot::write_boundaries(out, &self.cell_boundaries)?;
+        write!(out, "state")?;
+        for state in self.front.iter() {
+            write!(out, " {}", state)?;
+        }
+        writeln!(out)
+    }
+
+    pub fn load_snapshot(path: &path::Path) -> World {
+        let file = match fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) => panic!("Couldn't open snapshot '{}': {}", path.display(), e),
+        };
+        match World::read_snapshot(&mut snapshot::Reader::new(BufReader::new(file))) {
+            Ok(world) => world,
+            Err(e) => panic!("Couldn't load snapshot '{}': {}", path.display(), e),
+        }
+    }
+
+    fn read_snapshot<R: io::BufRead>(reader: &mut snapshot::Reader<R>) -> Result<World, String> {
+        if reader.expect("lifelike-snapshot")? != ["1"] {
+            return Err(reader.error("Unsupported snapshot version"));
+        }
+        let size: Vec<u32> = {
+            let words = reader.expect("size")?;
+            reader.parse_words(&words, 2)?
+        };
+        let (width, height) = (size[0], size[1]);
+        let wrap = {
+            let words = reader.expect("wrap")?;
+            reader.parse_words::<u8>(&words, 1)?[0] != 0
+        };
+        let generation: u64 = {
+            let words = reader.expect("generation")?;
+            reader.parse_words(&words, 1)?[0]
+        };
+
+        let mut rule = match Rule::parse(&reader.expect("rule")?.join(" ")) {
+            Ok(rule) => rule,
+            Err(e) => return Err(reader.error(&e)),
+        };
+        match Neighborhood::parse(&reader.expect("neighborhood")?.join(" ")) {
+            Ok(neighborhood) => rule.set_neighborhood(neighborhood),
+            Err(e) => return Err(reader.error(&e)),
+        }
+        match Weighting::parse(&reader.expect("weighting")?.join(" ")) {
+            Ok(weighting) => rule.set_weighting(weighting),
+            Err(e) => return Err(reader.error(&e)),
+        }
+
+        let cells = snapshot::read_cells(reader, width, height, true)?;
+        let cell_boundaries = snapshot::read_boundaries(reader, width, height, true)?;
+        let front: Vec<u8> = {
+            let words = reader.expect("state")?;
+            reader.parse_words(&words, cells.len())?
+        };
+        if front.iter().any(|state| *state >= rule.states()) {
+            return Err(reader.error("Cell state out of range for the rule"));
+        }
+
+        let cell_map = cell_map_from_cells(width, height, &cells);
+        let mut world = World::new(
+            front,
+            repeat(DEAD).take(cells.len()).collect(),
+            Image::transparent(width, height),
+            cell_boundaries,
+            cell_map,
+            wrap,
+            rule,
+            cells,
+        );
+        world.stats.generation = generation;
+        world.history.clear();
+        world.record_history();
+        Ok(world)
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    pub fn set_render_mode(&mut self, render_mode: RenderMode, trail: u32) {
+        self.render_mode = render_mode;
+        self.trail = trail;
+    }
+
+    // Generations the cell has been alive in a row, or 0 if it isn't alive.
+    pub fn cell_age(&self, cell: usize) -> u32 {
+        self.ages[cell]
+    }
+
+    // Times the cell has been born or died since the world was created or resumed.
+    pub fn cell_flips(&self, cell: usize) -> u32 {
+        self.flips[cell]
+    }
+
+    pub fn rule(&self) -> &Rule {
+        &self.rule
+    }
+
+    pub fn wrap(&self) -> bool {
+        self.wrap
     }
 
     // We'll want to borrow this to write the state to disk.
@@ -114,3 +497,183 @@ impl World {
         &self.image
     }
 }
+
+// For cells that weren't found by flooding an image, e.g. loaded from disk.
+pub fn cell_map_from_cells(width: u32, height: u32, cells: &[Cell]) -> Vec<Option<usize>> {
+    let mut cell_map: Vec<Option<usize>> = repeat(None).take((width * height) as usize).collect();
+    for (i, cell) in cells.iter().enumerate() {
+        for p in cell.pixels.iter() {
+            cell_map[p.y as usize * width as usize + p.x as usize] = Some(i);
+        }
+    }
+    cell_map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // Two cells side by side over a strip of boundary pixels, and a third
+    // below touching the first only at a corner.
+    fn sample_world() -> World {
+        let cells = vec![
+            Cell{
+                color: Color{ red: 255, green: 0, blue: 0, alpha: 255 },
+                neighbors: vec![
+                    Neighbor{ cell: 1, adjacency: Adjacency::Edge, border: 2 },
+                    Neighbor{ cell: 2, adjacency: Adjacency::Corner, border: 0 },
+                ],
+                pixels: vec![Point{ x: 0, y: 0 }, Point{ x: 1, y: 0 }, Point{ x: 0, y: 1 }],
+            },
+            Cell{
+                color: Color{ red: 0, green: 0, blue: 255, alpha: 128 },
+                neighbors: vec![Neighbor{ cell: 0, adjacency: Adjacency::Edge, border: 2 }],
+                pixels: vec![Point{ x: 3, y: 0 }, Point{ x: 3, y: 1 }],
+            },
+            Cell{
+                color: Color{ red: 0, green: 255, blue: 0, alpha: 255 },
+                neighbors: vec![Neighbor{ cell: 0, adjacency: Adjacency::Corner, border: 0 }],
+                pixels: vec![Point{ x: 1, y: 2 }, Point{ x: 2, y: 2 }, Point{ x: 3, y: 2 }],
+            },
+        ];
+        let mut boundaries = Image::white(4, 3);
+        boundaries.set_color_at(Point{ x: 2, y: 0 }, Color{ red: 0, green: 0, blue: 0, alpha: 255 });
+        boundaries.set_color_at(Point{ x: 2, y: 1 }, Color{ red: 10, green: 20, blue: 30, alpha: 200 });
+        let mut rule = Rule::parse("B2/S23/C3").unwrap();
+        rule.set_neighborhood(Neighborhood::Edge);
+        rule.set_weighting(Weighting::Degree);
+        let cell_map = cell_map_from_cells(4, 3, &cells);
+        let mut world = World::new(
+            vec![ALIVE, 2, DEAD],
+            vec![DEAD; 3],
+            Image::transparent(4, 3),
+            boundaries,
+            cell_map,
+            true,
+            rule,
+            cells,
+        );
+        world.stats.generation = 17;
+        world
+    }
+
+    // A row of one-pixel cells, each an edge neighbor of the next.
+    fn row_world(states: &[u8], rule: &str) -> World {
+        let count = states.len();
+        let cells: Vec<Cell> = (0..count).map(|i| Cell{
+            color: Color{ red: 255, green: 255, blue: 255, alpha: 255 },
+            neighbors: [i.wrapping_sub(1), i + 1].iter()
+                .filter(|&&other| other < count)
+                .map(|&other| Neighbor{ cell: other, adjacency: Adjacency::Edge, border: 1 })
+                .collect(),
+            pixels: vec![Point{ x: i as i32, y: 0 }],
+        }).collect();
+        let cell_map = cell_map_from_cells(count as u32, 1, &cells);
+        World::new(
+            states.to_vec(),
+            vec![DEAD; count],
+            Image::transparent(count as u32, 1),
+            Image::white(count as u32, 1),
+            cell_map,
+            false,
+            Rule::parse(rule).unwrap(),
+            cells,
+        )
+    }
+
+    fn same_color(a: Color, b: Color) -> bool {
+        (a.red, a.green, a.blue, a.alpha) == (b.red, b.green, b.blue, b.alpha)
+    }
+
+    fn pixels(cell: &Cell) -> Vec<(i32, i32)> {
+        let mut pixels: Vec<(i32, i32)> = cell.pixels.iter().map(|p| (p.x, p.y)).collect();
+        pixels.sort();
+        pixels
+    }
+
+    fn neighbors(cell: &Cell) -> Vec<(usize, bool, u32)> {
+        cell.neighbors.iter().map(|n| (n.cell, n.adjacency == Adjacency::Edge, n.border)).collect()
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let world = sample_world();
+        let mut buffer = Vec::new();
+        world.write_snapshot(&mut buffer).unwrap();
+        let loaded = World::read_snapshot(&mut snapshot::Reader::new(Cursor::new(buffer))).unwrap();
+
+        assert_eq!(loaded.cells.len(), world.cells.len());
+        for (a, b) in loaded.cells.iter().zip(world.cells.iter()) {
+            assert!(same_color(a.color, b.color));
+            assert_eq!(pixels(a), pixels(b));
+            assert_eq!(neighbors(a), neighbors(b));
+        }
+        assert_eq!(loaded.cell_map, world.cell_map);
+        assert_eq!(loaded.cell_boundaries.pixel_data, world.cell_boundaries.pixel_data);
+        assert_eq!(loaded.rule.to_string(), world.rule.to_string());
+        assert!(loaded.rule.neighborhood() == Neighborhood::Edge);
+        assert!(loaded.rule.weighting() == Weighting::Degree);
+        assert!(loaded.wrap);
+        assert_eq!(loaded.stats.generation, 17);
+        assert_eq!(loaded.front, world.front);
+    }
+
+    #[test]
+    fn still_life_has_period_one() {
+        let mut world = row_world(&[ALIVE, ALIVE, ALIVE], "B3/S12");
+        assert!(world.cycle().is_none());
+        world.step();
+        let cycle = world.cycle().unwrap();
+        assert_eq!((cycle.transient, cycle.period), (0, 1));
+    }
+
+    // 11000 -> 00100 -> 01010 -> 10001 -> 01010
+    #[test]
+    fn blinker_has_period_two_after_its_transient() {
+        let mut world = row_world(&[ALIVE, ALIVE, DEAD, DEAD, DEAD], "B1/S");
+        for _ in 0..3 {
+            world.step();
+            assert!(world.cycle().is_none());
+        }
+        world.step();
+        let cycle = world.cycle().unwrap();
+        assert_eq!((cycle.transient, cycle.period), (2, 2));
+
+        // Found once, it stays the same however long the world runs.
+        world.step();
+        let cycle = world.cycle().unwrap();
+        assert_eq!((cycle.transient, cycle.period), (2, 2));
+    }
+
+    #[test]
+    fn editing_a_cell_forgets_the_cycle() {
+        let mut world = row_world(&[ALIVE, ALIVE, DEAD, DEAD, DEAD], "B1/S");
+        for _ in 0..4 {
+            world.step();
+        }
+        assert!(world.cycle().is_some());
+        world.set_cell_state(0, ALIVE);
+        assert!(world.cycle().is_none());
+        // 11010 -> 00001, which was never seen before.
+        world.step();
+        assert!(world.cycle().is_none());
+    }
+
+    #[test]
+    fn set_rule_drops_states_the_rule_lacks() {
+        let mut world = sample_world();
+        world.set_rule(Rule::parse("B3/S23").unwrap());
+        assert_eq!(world.front, vec![ALIVE, DEAD, DEAD]);
+        assert_eq!(world.rule.to_string(), "B3/S23");
+    }
+
+    #[test]
+    fn snapshot_rejects_states_beyond_the_rule() {
+        let world = sample_world();
+        let mut buffer = Vec::new();
+        world.write_snapshot(&mut buffer).unwrap();
+        let text = String::from_utf8(buffer).unwrap().replace("state 1 2 0", "state 1 3 0");
+        assert!(World::read_snapshot(&mut snapshot::Reader::new(Cursor::new(text))).is_err());
+    }
+}
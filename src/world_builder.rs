@@ -1,10 +1,15 @@
 use std::iter::repeat;
 use std::collections::vec_deque::VecDeque;
+use std::path;
 
-use rand::{thread_rng, Rng};
+use rand::{Rng, SeedableRng};
+use rand::isaac::Isaac64Rng;
 
-use image::{Image, Color, Point};
-use world::{World, Cell};
+use image::{Image, Color, Point, Rect};
+use world::{self, World, Cell, Neighbor, Adjacency};
+use rule::{Rule, DEAD, ALIVE};
+use spec::{Spec, Region};
+use cell_cache;
 
 pub struct WorldBuilder {
     image: Image, // Source image.
@@ -17,24 +22,44 @@ pub struct WorldBuilder {
     // Per-cell scratch space so we don't need to allocate again for every cell we visit.
     cell_point_queue: VecDeque<Point>,
     wrap: bool,
-    smin: u32,
-    smax: u32,
-    rmin: u32,
-    rmax: u32,
-    proportional: bool,
+    // Colors of lines drawn between cells, which aren't cells themselves.
+    separators: Vec<Color>,
+    // Cells either side of a separator line up to this thick are neighbors.
+    separator_width: u32,
+    // Reuse the cells found last time for the same image, if we can.
+    use_cache: bool,
+    rule: Rule,
+    seed: u64,
+    // Cells mostly dark in this image start alive, instead of at random.
+    initial_image: Option<Image>,
+    density: f64,
+    soup_region: SoupRegion,
+}
+
+// Where cells may start alive at random.
+enum SoupRegion {
+    Everywhere,
+    // Cells with most of their pixels inside the rectangle.
+    Rect(Rect),
+    // Cells mostly dark in the mask image.
+    Mask(Image),
 }
 
 impl WorldBuilder {
-    pub fn new(
-        image: Image,
-        wrap: bool,
-        smin: u32,
-        smax: u32,
-        rmin: u32,
-        rmax: u32,
-        proportional: bool,
-    ) -> WorldBuilder {
+    pub fn new(image: Image, spec: &Spec) -> WorldBuilder {
         let pixels = (image.width * image.height) as usize;
+        let initial_image = spec.initial_image.as_ref().map(|initial_image_file| {
+            eprintln!("Loading initial state from '{}'.", initial_image_file);
+            load_matching_image(initial_image_file, &image, "Initial state")
+        });
+        let soup_region = match spec.region {
+            None => SoupRegion::Everywhere,
+            Some(Region::Rect(rect)) => SoupRegion::Rect(rect),
+            Some(Region::Mask(ref mask_file)) => {
+                eprintln!("Loading region mask from '{}'.", mask_file);
+                SoupRegion::Mask(load_matching_image(mask_file, &image, "Region mask"))
+            },
+        };
         let builder = WorldBuilder {
             cell_boundaries: Image::white(image.width, image.height),
             image: image,
@@ -42,22 +67,86 @@ impl WorldBuilder {
             cells: Vec::with_capacity(100),
             point_queue: VecDeque::with_capacity(pixels),
             cell_point_queue: VecDeque::with_capacity(pixels),
-            wrap: wrap,
-            smin: smin,
-            smax: smax,
-            rmin: rmin,
-            rmax: rmax,
-            proportional: proportional,
+            wrap: spec.wrap,
+            separators: spec.separators.clone(),
+            separator_width: spec.separator_width,
+            use_cache: spec.cache,
+            rule: spec.rule.clone(),
+            seed: spec.seed.expect("Seed must be chosen before building the world"),
+            initial_image: initial_image,
+            density: spec.density,
+            soup_region: soup_region,
         };
         builder
     }
 
     pub fn build(mut self) -> World {
+        let cache_file = if self.use_cache {
+            let key = cell_cache::key(&self.image, self.wrap, &self.separators, self.separator_width);
+            Some(cell_cache::path_for_key(key))
+        } else {
+            None
+        };
+        let cached = cache_file.as_ref().and_then(|cache_file| {
+            cell_cache::load(cache_file, self.image.width, self.image.height)
+        });
+        match cached {
+            Some((cells, cell_boundaries)) => {
+                eprintln!("Loaded {} cells from cache '{}'.", cells.len(), cache_file.unwrap().display());
+                self.cell_map = world::cell_map_from_cells(self.image.width, self.image.height, &cells);
+                self.cells = cells;
+                self.cell_boundaries = cell_boundaries;
+            },
+            None => {
+                self.find_cells();
+                if let Some(cache_file) = cache_file {
+                    cell_cache::save(&cache_file, &self.cells, &self.cell_boundaries);
+                }
+            },
+        }
+
+        let world = World::new(
+            self.initial_state(),
+            repeat(DEAD).take(self.cells.len()).collect(),
+            // Left transparent wherever there are no cells.
+            Image::transparent(self.image.width, self.image.height),
+            self.cell_boundaries,
+            self.cell_map,
+            self.wrap,
+            self.rule,
+            self.cells,
+        );
+        world
+    }
+
+    fn find_cells(&mut self) {
         // Explore image breadth-first to break it
         // into cells of the same color.
-        println!("Finding cells in image...");
+        eprintln!("Finding cells in image...");
+
+        // Transparent pixels can cut the image into separate pieces, which
+        // exploring from one corner would never reach; start again from the
+        // first pixel left over until there aren't any.
+        for y in 0..self.image.height as i32 {
+            for x in 0..self.image.width as i32 {
+                let point = Point{ x: x, y: y };
+                if self.is_separator(point) {
+                    // Separators are drawn as boundaries, in their own color.
+                    let color = self.image.color_at(point);
+                    self.cell_boundaries.set_color_at(point, color);
+                } else if self.cell_map[self.image.linear_index(point)] == None && !self.is_void(point) {
+                    self.point_queue.push_back(point);
+                    self.explore();
+                }
+            }
+        }
+        if !self.separators.is_empty() {
+            self.bridge_separators();
+        }
+        eprintln!("Found {} cells.", self.cells.len());
+    }
 
-        self.point_queue.push_back(Point{ x: 0, y: 0 });
+    fn explore(&mut self) {
         while !self.point_queue.is_empty() {
             let point = match self.point_queue.pop_front() {
                 None => panic!(),
@@ -79,24 +168,110 @@ impl WorldBuilder {
                 self.flood_cell(point, cell_index);
             }
         }
-        println!("Found {} cells.", self.cells.len());
+    }
 
-        // Randomise initial world state.
-        let mut rng = thread_rng();
-        let rng_iter = rng.gen_iter::<bool>();
-        let world = World::new(
-            rng_iter.take(self.cells.len()).collect(),
-            repeat(false).take(self.cells.len()).collect(),
-            Image::white(self.image.width, self.image.height),
-            self.cell_boundaries,
-            self.smin,
-            self.smax,
-            self.rmin,
-            self.rmax,
-            self.proportional,
-            self.cells,
-        );
-        world
+    // Fully transparent pixels are holes in the world, belonging to no cell.
+    fn is_void(&self, point: Point) -> bool {
+        self.image.color_at(point).alpha == 0
+    }
+
+    fn is_separator(&self, point: Point) -> bool {
+        let color = self.image.color_at(point);
+        self.separators.iter().any(|separator| *separator == color)
+    }
+
+    // The point itself, wrapped around if need be; `None` if it's off the edge.
+    fn in_image(&self, point: Point) -> Option<Point> {
+        let (width, height) = (self.image.width as i32, self.image.height as i32);
+        if self.wrap {
+            Some(Point{ x: (point.x % width + width) % width, y: (point.y % height + height) % height })
+        } else if point.x < 0 || point.y < 0 || point.x >= width || point.y >= height {
+            None
+        } else {
+            Some(point)
+        }
+    }
+
+    // Whether pixels on both sides of the diagonal step from `point` by
+    // (dx, dy) are on a line, e.g. of separators, passing between its ends.
+    fn cuts_corner<F: Fn(Point) -> bool>(&self, point: Point, dx: i32, dy: i32, on_line: F) -> bool {
+        let across = [Point{ x: point.x + dx, y: point.y }, Point{ x: point.x, y: point.y + dy }];
+        across.iter().all(|p| self.in_image(*p).map_or(false, &on_line))
+    }
+
+    // Link up cells on either side of thin separator lines, looking straight
+    // across them for edge neighbors and diagonally for corner neighbors.
+    fn bridge_separators(&mut self) {
+        let directions = [
+            (1, 0, Adjacency::Edge), (-1, 0, Adjacency::Edge), (0, 1, Adjacency::Edge), (0, -1, Adjacency::Edge),
+            (1, 1, Adjacency::Corner), (-1, 1, Adjacency::Corner), (1, -1, Adjacency::Corner), (-1, -1, Adjacency::Corner),
+        ];
+        for cell_index in 0..self.cells.len() {
+            for pixel_index in 0..self.cells[cell_index].pixels.len() {
+                let start = self.cells[cell_index].pixels[pixel_index];
+                for &(dx, dy, adjacency) in directions.iter() {
+                    if let Some(other_cell) = self.across_separator(start, dx, dy) {
+                        // Each crossing is seen from both sides; count it once.
+                        if other_cell > cell_index {
+                            self.link_cells(cell_index, other_cell, adjacency);
+                            if adjacency == Adjacency::Edge {
+                                self.add_border(cell_index, other_cell);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // The cell on the far side of the separator line next to `start` in the
+    // given direction, if there is one and the line is thin enough.
+    fn across_separator(&self, start: Point, dx: i32, dy: i32) -> Option<usize> {
+        let mut point = start;
+        for steps in 0..self.separator_width + 1 {
+            point = match self.in_image(Point{ x: point.x + dx, y: point.y + dy }) {
+                Some(point) => point,
+                None => return None,
+            };
+            if !self.is_separator(point) {
+                // Touching directly is already taken care of, unless a
+                // diagonal line passes between the two pixels.
+                if (steps == 0 && !self.cuts_corner(start, dx, dy, |p| self.is_separator(p))) || self.is_void(point) {
+                    return None;
+                }
+                return self.cell_map[self.image.linear_index(point)];
+            }
+        }
+        None
+    }
+
+    fn initial_state(&self) -> Vec<u8> {
+        match self.initial_image {
+            Some(ref initial_image) => {
+                self.cells.iter().map(|cell| if mostly_dark(initial_image, cell) { ALIVE } else { DEAD }).collect()
+            },
+            None => {
+                // Randomise initial world state.
+                let mut rng = seeded_rng(self.seed);
+                self.cells.iter().map(|cell| {
+                    // Always draw, so the region doesn't change which cells
+                    // within it come alive for a given seed.
+                    let lucky = rng.gen::<f64>() < self.density;
+                    if lucky && self.in_soup_region(cell) { ALIVE } else { DEAD }
+                }).collect()
+            },
+        }
+    }
+
+    fn in_soup_region(&self, cell: &Cell) -> bool {
+        match self.soup_region {
+            SoupRegion::Everywhere => true,
+            SoupRegion::Rect(ref rect) => {
+                let inside = cell.pixels.iter().filter(|p| rect.contains(**p)).count();
+                inside * 2 > cell.pixels.len()
+            },
+            SoupRegion::Mask(ref mask) => mostly_dark(mask, cell),
+        }
     }
 
     fn flood_cell(&mut self, starting_point: Point, cell_index: usize) {
@@ -109,9 +284,18 @@ impl WorldBuilder {
                 Some(p) => p,
             };
             self.cells[cell_index].pixels.push(point);
-            let neighbors = point.neighbors();
-            for neighbor in neighbors.iter() {
-                let mut neighbor = *neighbor;
+            let edge_neighbors = point.edge_neighbors();
+            let corner_neighbors = point.corner_neighbors();
+            let neighbors = edge_neighbors.iter().map(|p| (*p, Adjacency::Edge))
+                .chain(corner_neighbors.iter().map(|p| (*p, Adjacency::Corner)));
+            for (neighbor, adjacency) in neighbors {
+                // Pixels only touching across a diagonal line of holes or separators
+                // are kept apart; separators are linked across later, holes aren't.
+                let (dx, dy) = (neighbor.x - point.x, neighbor.y - point.y);
+                if adjacency == Adjacency::Corner && self.cuts_corner(point, dx, dy, |p| self.is_separator(p) || self.is_void(p)) {
+                    continue;
+                }
+                let mut neighbor = neighbor;
 
                 // Wrap coordinates if requested.
                 // Note that the result of % depends on the sign of the divisor,
@@ -136,6 +320,16 @@ impl WorldBuilder {
                     continue;
                 }
 
+                // Holes have edges just like the image does.
+                if self.is_void(neighbor) {
+                    self.mark_cell_border(point);
+                    continue;
+                }
+                // Separator lines are already boundaries; cells are linked across them later.
+                if self.is_separator(neighbor) {
+                    continue;
+                }
+
                 let neighbor_cell = self.cell_map[self.image.linear_index(neighbor)];
                 match neighbor_cell {
                     None => {
@@ -161,10 +355,12 @@ impl WorldBuilder {
                             // Mark the current pixel (not the neighbor) as the edge of a cell.
                             self.mark_cell_border(point);
 
-                            // Mark the cells as neighbors if they're not already.
-                            if !(*self.cells)[cell_index].neighbors.contains(&neighbor_cell) {
-                                self.cells[cell_index].neighbors.push(neighbor_cell);
-                                self.cells[neighbor_cell].neighbors.push(cell_index);
+                            self.link_cells(cell_index, neighbor_cell, adjacency);
+
+                            // Each pair of pixels is only seen here once, from whichever
+                            // cell was found second, so this tallies the shared border.
+                            if adjacency == Adjacency::Edge {
+                                self.add_border(cell_index, neighbor_cell);
                             }
                         }
                     }
@@ -173,7 +369,219 @@ impl WorldBuilder {
         }
     }
 
+    // Mark the cells as neighbors if they're not already. Cells that touch
+    // both at a corner and along an edge somewhere count as edge neighbors.
+    fn link_cells(&mut self, a: usize, b: usize, adjacency: Adjacency) {
+        let existing = self.cells[a].neighbors.iter().position(|neighbor| neighbor.cell == b);
+        match existing {
+            None => {
+                self.cells[a].neighbors.push(Neighbor{ cell: b, adjacency: adjacency, border: 0 });
+                self.cells[b].neighbors.push(Neighbor{ cell: a, adjacency: adjacency, border: 0 });
+            },
+            Some(index_in_a) => {
+                if adjacency == Adjacency::Edge {
+                    self.cells[a].neighbors[index_in_a].adjacency = Adjacency::Edge;
+                    for neighbor in self.cells[b].neighbors.iter_mut() {
+                        if neighbor.cell == a {
+                            neighbor.adjacency = Adjacency::Edge;
+                        }
+                    }
+                }
+            },
+        }
+    }
+
+    fn add_border(&mut self, a: usize, b: usize) {
+        for neighbor in self.cells[a].neighbors.iter_mut() {
+            if neighbor.cell == b {
+                neighbor.border += 1;
+            }
+        }
+        for neighbor in self.cells[b].neighbors.iter_mut() {
+            if neighbor.cell == a {
+                neighbor.border += 1;
+            }
+        }
+    }
+
     fn mark_cell_border(&mut self, point: Point) {
-        self.cell_boundaries.set_color_at(point, Color{red: 127, green: 127, blue: 127});
+        self.cell_boundaries.set_color_at(point, Color{red: 127, green: 127, blue: 127, alpha: 255});
+    }
+}
+
+fn load_matching_image(file: &str, image: &Image, description: &str) -> Image {
+    let other = Image::load_png(&path::Path::new(file));
+    if other.width != image.width || other.height != image.height {
+        panic!(
+            "{} image '{}' is {}x{}, but the input image is {}x{}; they must be the same size.",
+            description, file, other.width, other.height, image.width, image.height
+        );
+    }
+    other
+}
+
+// Whether more than half of the cell's pixels are dark in the given image.
+// Mostly transparent pixels don't count as dark, whatever their color.
+fn mostly_dark(image: &Image, cell: &Cell) -> bool {
+    let dark_pixels = cell.pixels.iter().filter(|p| {
+        let color = image.color_at(**p);
+        let luma = (299 * color.red as u32 + 587 * color.green as u32 + 114 * color.blue as u32) / 1000;
+        color.alpha >= 128 && luma < 128
+    }).count();
+    dark_pixels * 2 > cell.pixels.len()
+}
+
+// Isaac64 by name rather than StdRng, which is only Isaac64 on 64-bit
+// targets, so a seed gives the same sequence on 32-bit ones too. The seed
+// words are the ones StdRng got on 64-bit, which keeps existing seeds'
+// worlds the same there.
+pub fn seeded_rng(seed: u64) -> Isaac64Rng {
+    let seed_words = [seed & 0xffff_ffff, seed >> 32];
+    SeedableRng::from_seed(&seed_words[..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rule::{Weighting, Thresholds};
+
+    // Two white triangles either side of a one pixel black line
+    // from corner to corner.
+    fn split_square(size: u32) -> Image {
+        let mut image = Image::white(size, size);
+        for i in 0..size as i32 {
+            image.set_color_at(Point{ x: i, y: i }, Color{ red: 0, green: 0, blue: 0, alpha: 255 });
+        }
+        image
+    }
+
+    fn spec(separators: Vec<Color>) -> Spec {
+        let mut spec = Spec::default();
+        spec.separators = separators;
+        spec.cache = false;
+        spec.seed = Some(1);
+        spec
+    }
+
+    fn build(image: Image, spec: &Spec) -> World {
+        WorldBuilder::new(image, spec).build()
+    }
+
+    fn opaque(red: u8, green: u8, blue: u8) -> Color {
+        Color{ red: red, green: green, blue: blue, alpha: 255 }
+    }
+
+    // R R G
+    // R R G
+    // B B B
+    fn three_cells() -> Image {
+        let mut image = Image::white(3, 3);
+        for y in 0..3 {
+            for x in 0..3 {
+                let color = match (x, y) {
+                    (_, 2) => opaque(0, 0, 255),
+                    (2, _) => opaque(0, 255, 0),
+                    _ => opaque(255, 0, 0),
+                };
+                image.set_color_at(Point{ x: x, y: y }, color);
+            }
+        }
+        image
+    }
+
+    fn border(world: &World, from: usize, to: usize) -> u32 {
+        let neighbor = world.cell_neighbors(from).iter().find(|neighbor| neighbor.cell == to).unwrap();
+        assert!(neighbor.adjacency == Adjacency::Edge);
+        neighbor.border
+    }
+
+    #[test]
+    fn diagonal_separator_splits_cells() {
+        let black = Color{ red: 0, green: 0, blue: 0, alpha: 255 };
+        let world = build(split_square(5), &spec(vec![black]));
+        assert_eq!(world.cell_count(), 2);
+        let above = world.cell_at(Point{ x: 1, y: 0 }).unwrap();
+        let below = world.cell_at(Point{ x: 0, y: 1 }).unwrap();
+        assert!(above != below);
+        assert_eq!(world.cell_pixel_count(above), 10);
+        assert_eq!(world.cell_pixel_count(below), 10);
+        assert_eq!(world.cell_at(Point{ x: 2, y: 2 }), None);
+
+        let neighbors = world.cell_neighbors(above);
+        assert_eq!(neighbors.len(), 1);
+        assert_eq!(neighbors[0].cell, below);
+        assert!(neighbors[0].adjacency == Adjacency::Edge);
+    }
+
+    // A white triangle above a transparent diagonal, and one of the given
+    // color below it.
+    fn holed_square(below: Color) -> Image {
+        let mut image = Image::white(5, 5);
+        for y in 0..5 {
+            for x in 0..y + 1 {
+                let color = if x == y { Color{ red: 0, green: 0, blue: 0, alpha: 0 } } else { below };
+                image.set_color_at(Point{ x: x, y: y }, color);
+            }
+        }
+        image
+    }
+
+    // Holes split cells the same way, but nothing is linked across them.
+    #[test]
+    fn diagonal_hole_splits_cells() {
+        for &below in [opaque(255, 255, 255), opaque(255, 0, 0)].iter() {
+            let world = build(holed_square(below), &spec(Vec::new()));
+            assert_eq!(world.cell_count(), 2);
+            let above = world.cell_at(Point{ x: 1, y: 0 }).unwrap();
+            let below = world.cell_at(Point{ x: 0, y: 1 }).unwrap();
+            assert!(above != below);
+            assert_eq!(world.cell_pixel_count(above), 10);
+            assert_eq!(world.cell_pixel_count(below), 10);
+            assert!(world.cell_neighbors(above).is_empty());
+            assert!(world.cell_neighbors(below).is_empty());
+        }
+    }
+
+    // An ordinary line is a cell of its own, and the same color meeting
+    // diagonally across it is still one cell, as in pixel art.
+    #[test]
+    fn diagonal_line_without_separators_joins_cells() {
+        let world = build(split_square(5), &spec(Vec::new()));
+        assert_eq!(world.cell_count(), 2);
+        assert_eq!(world.cell_at(Point{ x: 1, y: 0 }), world.cell_at(Point{ x: 0, y: 1 }));
+    }
+
+    // Every shared pixel edge counts once, on both sides, including those
+    // that meet across the edge of a wrapped image.
+    #[test]
+    fn borders_count_each_pixel_edge_once() {
+        let mut spec = spec(Vec::new());
+        spec.rule.set_weighting(Weighting::Border(Thresholds{
+            survival_min: 1.0, survival_max: 1.0, birth_min: 0.6, birth_max: 0.7,
+        }));
+        for &(wrap, scale) in [(false, 1), (true, 2)].iter() {
+            spec.wrap = wrap;
+            let mut world = build(three_cells(), &spec);
+            assert_eq!(world.cell_count(), 3);
+            let red = world.cell_at(Point{ x: 0, y: 0 }).unwrap();
+            let green = world.cell_at(Point{ x: 2, y: 0 }).unwrap();
+            let blue = world.cell_at(Point{ x: 0, y: 2 }).unwrap();
+            for &(a, b, expected) in [(red, green, 2), (red, blue, 2), (green, blue, 1)].iter() {
+                assert_eq!(border(&world, a, b), expected * scale);
+                assert_eq!(border(&world, b, a), expected * scale);
+            }
+
+            for cell in 0..3 {
+                world.set_cell_state(cell, if cell == red { ALIVE } else { DEAD });
+            }
+            assert_eq!(world.living_border_fraction(red), 0.0);
+            assert_eq!(world.living_border_fraction(green), 2.0 / 3.0);
+            assert_eq!(world.living_border_fraction(blue), 2.0 / 3.0);
+            world.step();
+            assert_eq!(world.cell_state(red), DEAD);
+            assert_eq!(world.cell_state(green), ALIVE);
+            assert_eq!(world.cell_state(blue), ALIVE);
+            assert_eq!(world.living_border_fraction(red), 1.0);
+        }
     }
 }